@@ -0,0 +1,161 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::util::LineEnding;
+
+/// Version 1 bundles have no `options`; they replay with the options given on the command line.
+const BUNDLE_VERSION: u64 = 2;
+
+/// Everything needed to reproduce one generation: what was asked, how, and what came back.
+pub struct Bundle {
+    pub task: String,
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: u16,
+    pub prompt: String,
+    pub program: String,
+    pub input: String,
+    /// `None` for a version 1 bundle.
+    pub options: Option<RunOptions>,
+}
+
+/// The options that decide how the bundled program runs and how its result is written.
+pub struct RunOptions {
+    pub vars: Vec<(String, String)>,
+    pub result_var: String,
+    pub jsonify: bool,
+    pub jsonify_one_line: bool,
+    pub split_output: Option<PathBuf>,
+    pub line_endings: LineEnding,
+    pub strict_result: bool,
+    pub result_hook: Option<String>,
+    pub safe: bool,
+    pub allow_modules: Vec<String>,
+    pub timeout: Option<Duration>,
+}
+
+impl RunOptions {
+    fn to_json(&self) -> Value {
+        json!({
+            "vars": Value::Object(self.vars.iter().map(|(name, value)| (name.clone(), value.as_str().into())).collect()),
+            "result_var": self.result_var,
+            "json": self.jsonify,
+            "json_one_line": self.jsonify_one_line,
+            "split_output": self.split_output.as_ref().map(|dir| dir.display().to_string()),
+            "line_endings": self.line_endings.name(),
+            "strict_result": self.strict_result,
+            "result_hook": self.result_hook,
+            "safe": self.safe,
+            "allow_modules": self.allow_modules,
+            "timeout": self.timeout.map(|limit| limit.as_secs()),
+        })
+    }
+
+    fn from_json(options: &Value) -> Result<RunOptions, Box<dyn Error>> {
+        let field = |key: &str| options.get(key).ok_or_else(|| format!("missing 'options.{}'", key));
+        let invalid = |key: &str| format!("invalid 'options.{}'", key);
+        let flag = |key: &str| -> Result<bool, Box<dyn Error>> { Ok(field(key)?.as_bool().ok_or_else(|| invalid(key))?) };
+        let optional_string = |key: &str| -> Result<Option<String>, Box<dyn Error>> {
+            match field(key)? {
+                Value::Null => Ok(None),
+                Value::String(s) => Ok(Some(s.clone())),
+                _ => Err(invalid(key).into()),
+            }
+        };
+
+        let vars = field("vars")?
+            .as_object()
+            .ok_or_else(|| invalid("vars"))?
+            .iter()
+            .map(|(name, value)| Some((name.clone(), value.as_str()?.to_owned())))
+            .collect::<Option<_>>()
+            .ok_or_else(|| invalid("vars"))?;
+        let allow_modules = field("allow_modules")?
+            .as_array()
+            .ok_or_else(|| invalid("allow_modules"))?
+            .iter()
+            .map(|module| module.as_str().map(|s| s.to_owned()))
+            .collect::<Option<_>>()
+            .ok_or_else(|| invalid("allow_modules"))?;
+        let timeout = match field("timeout")? {
+            Value::Null => None,
+            secs => Some(Duration::from_secs(secs.as_u64().ok_or_else(|| invalid("timeout"))?)),
+        };
+
+        Ok(RunOptions {
+            vars,
+            result_var: optional_string("result_var")?.ok_or_else(|| invalid("result_var"))?,
+            jsonify: flag("json")?,
+            jsonify_one_line: flag("json_one_line")?,
+            split_output: optional_string("split_output")?.map(PathBuf::from),
+            line_endings: optional_string("line_endings")?.ok_or_else(|| invalid("line_endings"))?.parse()?,
+            strict_result: flag("strict_result")?,
+            result_hook: optional_string("result_hook")?,
+            safe: flag("safe")?,
+            allow_modules,
+            timeout,
+        })
+    }
+}
+
+impl Bundle {
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let bundle = json!({
+            "bundle_version": BUNDLE_VERSION,
+            "gptxt_version": env!("CARGO_PKG_VERSION"),
+            "task": self.task,
+            "model": self.model,
+            "temperature": self.temperature,
+            "max_tokens": self.max_tokens,
+            "prompt": self.prompt,
+            "program": self.program,
+            "input": self.input,
+            "options": self.options.as_ref().map(RunOptions::to_json),
+        });
+        fs::write(path, serde_json::to_string_pretty(&bundle)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Bundle, Box<dyn Error>> {
+        let bundle: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        let version = match bundle.get("bundle_version").and_then(Value::as_u64) {
+            Some(v @ 1..=BUNDLE_VERSION) => v,
+            Some(v) => return Err(format!("unsupported bundle version {}", v).into()),
+            None => return Err("missing 'bundle_version'".into()),
+        };
+
+        let string = |key: &str| -> Result<String, Box<dyn Error>> {
+            bundle
+                .get(key)
+                .and_then(Value::as_str)
+                .map(|s| s.to_owned())
+                .ok_or_else(|| format!("missing or non-string '{}'", key).into())
+        };
+        let number = |key: &str| -> Result<f64, Box<dyn Error>> {
+            bundle
+                .get(key)
+                .and_then(Value::as_f64)
+                .ok_or_else(|| format!("missing or non-numeric '{}'", key).into())
+        };
+        let options = match version {
+            1 => None,
+            _ => Some(RunOptions::from_json(bundle.get("options").ok_or("missing 'options'")?)?),
+        };
+
+        Ok(Bundle {
+            task: string("task")?,
+            model: string("model")?,
+            temperature: number("temperature")? as f32,
+            max_tokens: number("max_tokens")? as u16,
+            prompt: string("prompt")?,
+            program: string("program")?,
+            input: string("input")?,
+            options,
+        })
+    }
+}
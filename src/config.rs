@@ -0,0 +1,241 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+#[derive(Default)]
+pub struct Config {
+    /// `key` followed by any `keys`; never empty once the config is read.
+    pub keys: Vec<String>,
+    /// API root for OpenAI-compatible servers, always ending in `/`.
+    pub base_url: Option<String>,
+    pub system_stdin_line: Option<bool>,
+    pub system_file: Option<String>,
+    pub system_append: Option<String>,
+    pub default_format: Option<String>,
+    pub default_output: Option<String>,
+    pub model: Option<String>,
+    pub models: Option<Vec<String>>,
+    pub prompt_tty: Option<bool>,
+    pub theme: Option<String>,
+    pub temp_dir: Option<String>,
+    pub editor: Option<String>,
+    pub alt_screen: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A blank config file was written; the user needs to fill in the key.
+    Created(PathBuf),
+    MissingKey(PathBuf),
+    EmptyKey(PathBuf),
+    Read(Box<dyn Error>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Created(path) =>
+                write!(f, "Created a new configuration file at: {}", path.display()),
+            ConfigError::MissingKey(path) =>
+                write!(f, "The 'key' value is not set in the configuration file: {}", path.display()),
+            ConfigError::EmptyKey(path) =>
+                write!(f, "Set the 'key' value in the configuration file before using the program: {}", path.display()),
+            ConfigError::Read(err) =>
+                write!(f, "could not read config file: {}", err),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Read(Box::new(err))
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Read(Box::new(err))
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(err: serde_json::Error) -> Self {
+        ConfigError::Read(Box::new(err))
+    }
+}
+
+impl From<serde_yaml::Error> for ConfigError {
+    fn from(err: serde_yaml::Error) -> Self {
+        ConfigError::Read(Box::new(err))
+    }
+}
+
+/// The first of `gptxt.toml`, `gptxt.json`, `gptxt.yaml`, and `gptxt.yml` that exists in the
+/// config directory, or `gptxt.toml` if none do.
+pub fn config_path() -> Result<PathBuf, ConfigError> {
+    let config_dir = dirs::config_dir().ok_or_else(|| ConfigError::Read("Unable to find config directory".into()))?;
+    let path = ["toml", "json", "yaml", "yml"]
+        .iter()
+        .map(|ext| config_dir.join(format!("gptxt.{}", ext)))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| config_dir.join("gptxt.toml"));
+    Ok(path)
+}
+
+/// Reads the config file at `path`, or the default location if not given. Only the default
+/// location is created when missing, and only if the key isn't set in the environment either.
+pub fn read_or_create_config(path: Option<&Path>) -> Result<Config, ConfigError> {
+    let env_key = env_key();
+    if let Some(path) = path {
+        if !path.exists() {
+            return Err(ConfigError::Read(format!("{} does not exist", path.display()).into()));
+        }
+    }
+    let config_path = match path {
+        Some(path) => path.to_path_buf(),
+        None => config_path()?,
+    };
+
+    if !config_path.exists() {
+        if let Some(key) = env_key {
+            return Ok(Config {
+                keys: vec![key],
+                ..Config::default()
+            });
+        }
+    }
+
+    if let Some(config_dir) = config_path.parent() {
+        if !config_dir.exists() {
+            fs::create_dir_all(config_dir)?;
+        }
+    }
+
+    if !config_path.exists() {
+        let mut file = File::create(&config_path)?;
+        file.write_all(br#"key = """#)?;
+        return Err(ConfigError::Created(config_path));
+    }
+
+    let config = parse_config(&config_path, &fs::read_to_string(&config_path)?)?;
+
+    // `keys` adds keys to rotate through when one is rate limited; it can stand in for `key`.
+    // A key in the environment takes the place of `key`.
+    let config_key = config.get("key").map(|key| key.as_str().unwrap_or("").to_string());
+    let mut keys: Vec<String> = env_key.or(config_key).into_iter().collect();
+    if let Some(more) = config.get("keys").and_then(|v| v.as_array()) {
+        for key in more.iter().filter_map(|k| k.as_str()) {
+            if !keys.iter().any(|k| k == key) {
+                keys.push(key.to_owned());
+            }
+        }
+    }
+
+    if keys.is_empty() {
+        return Err(ConfigError::MissingKey(config_path));
+    }
+    if keys.iter().any(String::is_empty) {
+        return Err(ConfigError::EmptyKey(config_path));
+    }
+
+    let base_url = match get_string(&config, "base_url") {
+        Some(url) if !url.starts_with("http://") && !url.starts_with("https://") => {
+            return Err(ConfigError::Read(format!("'base_url' must start with http:// or https://, got '{}'", url).into()));
+        }
+        Some(url) if url.ends_with('/') => Some(url),
+        Some(url) => Some(url + "/"),
+        None => None,
+    };
+
+    let system_stdin_line = config.get("system_stdin_line").and_then(|v| v.as_bool());
+    let prompt_tty = config.get("prompt_tty").and_then(|v| v.as_bool());
+    let alt_screen = config.get("alt_screen").and_then(|v| v.as_bool());
+    let system_file = get_string(&config, "system_file");
+    let system_append = get_string(&config, "system_append");
+    let default_format = get_string(&config, "default_format");
+    let default_output = get_string(&config, "default_output");
+    let theme = get_string(&config, "theme");
+    let temp_dir = get_string(&config, "temp_dir");
+    let editor = get_string(&config, "editor");
+    let model = get_string(&config, "model");
+    if model.as_deref().is_some_and(|m| m.trim().is_empty()) {
+        return Err(ConfigError::Read("'model' is empty; remove it or give a model name".into()));
+    }
+    let models = config.get("models").and_then(|v| v.as_array()).map(|models| {
+        models
+            .iter()
+            .filter_map(|m| m.as_str().map(|s| s.to_owned()))
+            .collect()
+    });
+    // Used through `read_default_flags`, before the command line is parsed; only checked here.
+    default_flags(&config)?;
+
+    Ok(Config {
+        keys,
+        base_url,
+        system_stdin_line,
+        system_file,
+        system_append,
+        default_format,
+        default_output,
+        model,
+        models,
+        prompt_tty,
+        theme,
+        temp_dir,
+        editor,
+        alt_screen,
+    })
+}
+
+/// Only the `default_flags` of the config file at `path` (or the default location), which are
+/// needed before the rest of the config. Nothing is created, and a missing file has none.
+pub fn read_default_flags(path: Option<&Path>) -> Result<Vec<String>, ConfigError> {
+    let config_path = match path {
+        Some(path) => path.to_path_buf(),
+        None => config_path()?,
+    };
+    if !config_path.exists() {
+        return Ok(Vec::new());
+    }
+    default_flags(&parse_config(&config_path, &fs::read_to_string(&config_path)?)?)
+}
+
+fn default_flags(config: &Value) -> Result<Vec<String>, ConfigError> {
+    match config.get("default_flags") {
+        None => Ok(Vec::new()),
+        Some(v) => v
+            .as_array()
+            .and_then(|flags| flags.iter().map(|f| f.as_str().map(|s| s.to_owned())).collect())
+            .ok_or_else(|| ConfigError::Read("'default_flags' must be an array of strings".into())),
+    }
+}
+
+/// Parses `contents` as JSON or YAML based on the extension of `path`, and as TOML otherwise.
+fn parse_config(path: &Path, contents: &str) -> Result<Value, ConfigError> {
+    let value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str::<Value>(contents)?,
+        Some("yaml" | "yml") => serde_yaml::from_str::<Value>(contents)?,
+        _ => contents.parse::<Value>()?,
+    };
+    Ok(value)
+}
+
+/// `GPTXT_API_KEY`, or else `OPENAI_API_KEY`, if either is set and not empty.
+fn env_key() -> Option<String> {
+    ["GPTXT_API_KEY", "OPENAI_API_KEY"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|key| !key.trim().is_empty())
+}
+
+fn get_string(config: &Value, key: &str) -> Option<String> {
+    config.get(key).and_then(|v| v.as_str()).map(|s| s.to_owned())
+}
@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::execute::{self, emit_program_stdout, ExecOptions, ExecuteError};
+
+/// How many entries the cache keeps; the least recently written are removed past this.
+const MAX_ENTRIES: usize = 256;
+
+/// Results and printed output larger than this together aren't cached.
+const MAX_ENTRY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Like `execute::execute_program`, but returns the cached result of an identical earlier run if
+/// there is one, and caches the result otherwise. A cached result can't reproduce the scope dump,
+/// so `dump_scope` isn't supported here.
+pub async fn execute_program(
+    input: &str,
+    program: &str,
+    options: &ExecOptions<'_>,
+    verbose: bool,
+) -> Result<String, ExecuteError> {
+    let described = format!(
+        "{}\0{:?}\0{:?}\0{}\0{:?}\0{:?}\0{:?}",
+        options.strict,
+        options.sandbox,
+        options.result_hook,
+        options.result_var(),
+        options.vars,
+        options.timeout,
+        options.stdout_path
+    );
+    // What the program prints is written to a file of our own, so it can be stored with the
+    // result and shown again on a later hit.
+    let opened = CacheEntry::new(program, input, &described).and_then(|entry| Ok((entry, tempfile::tempdir()?)));
+    let (entry, printed_dir) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            print_warn!("could not open the execution cache: {}", e);
+            return execute::execute_program(input, program, options).await;
+        }
+    };
+
+    if let Some((cached, printed)) = entry.get() {
+        if verbose {
+            print_progress!("using the cached result of a previous run (run without --exec-cache to run again)");
+        }
+        if let Some(printed) = printed {
+            emit_program_stdout(&printed, options.stdout_path);
+        }
+        return Ok(cached);
+    }
+
+    let printed_path = printed_dir.path().join("stdout");
+    let result = execute::execute_program(
+        input,
+        program,
+        &ExecOptions { stdout_path: Some(&printed_path), ..*options },
+    )
+    .await;
+    // Not written when the printed output stood in for the result.
+    let printed = fs::read_to_string(&printed_path).ok();
+    if let Some(printed) = &printed {
+        emit_program_stdout(printed, options.stdout_path);
+    }
+    if let Ok(v) = &result {
+        if let Err(e) = entry.put(v, printed.as_deref()) {
+            print_warn!("could not write to the execution cache: {}", e);
+        }
+    }
+    result
+}
+
+/// The cached result of running one program on one input with the same execution options.
+pub struct CacheEntry {
+    dir: PathBuf,
+    path: PathBuf,
+    program: String,
+    options: String,
+    input_digest: String,
+}
+
+impl CacheEntry {
+    /// `options` describes everything other than the program and input that can change the result.
+    pub fn new(program: &str, input: &str, options: &str) -> Result<CacheEntry, Box<dyn Error>> {
+        let data_dir = dirs::data_dir().ok_or("Unable to find data directory")?;
+        let dir = data_dir.join("gptxt").join("exec-cache");
+
+        if !dir.exists() {
+            fs::create_dir_all(&dir)?;
+        }
+
+        let key = sha256(&format!("{}\0{}\0{}", options, program, input));
+
+        Ok(CacheEntry {
+            path: dir.join(format!("{}.json", key)),
+            dir,
+            program: program.to_owned(),
+            options: options.to_owned(),
+            input_digest: sha256(input),
+        })
+    }
+
+    /// The cached result and, unless it stood in for the result, what the program printed.
+    pub fn get(&self) -> Option<(String, Option<String>)> {
+        let entry: Value = serde_json::from_str(&fs::read_to_string(&self.path).ok()?).ok()?;
+
+        // Checked in full, so an entry is only ever used for exactly this program, input, and options.
+        if entry.get("program")?.as_str()? != self.program
+            || entry.get("options")?.as_str()? != self.options
+            || entry.get("input_sha256")?.as_str()? != self.input_digest
+        {
+            return None;
+        }
+        let result = entry.get("result")?.as_str()?.to_owned();
+        let printed = entry.get("printed").and_then(Value::as_str).map(|s| s.to_owned());
+        Some((result, printed))
+    }
+
+    pub fn put(&self, result: &str, printed: Option<&str>) -> Result<(), Box<dyn Error>> {
+        if result.len() + printed.map_or(0, str::len) > MAX_ENTRY_BYTES {
+            return Ok(());
+        }
+        let entry = json!({
+            "program": self.program,
+            "options": self.options,
+            "input_sha256": self.input_digest,
+            "result": result,
+            "printed": printed,
+        });
+        fs::write(&self.path, serde_json::to_string(&entry)?)?;
+        self.evict()
+    }
+
+    /// Removes the oldest entries once there are more than `MAX_ENTRIES`.
+    fn evict(&self) -> Result<(), Box<dyn Error>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.path().extension().is_some_and(|ext| ext == "json") {
+                entries.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        if entries.len() <= MAX_ENTRIES {
+            return Ok(());
+        }
+        entries.sort();
+        for (_, path) in &entries[..entries.len() - MAX_ENTRIES] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn sha256(s: &str) -> String {
+    Sha256::digest(s.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,341 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rustpython::vm;
+use rustpython::vm::PyObjectRef;
+
+use crate::{isolate, sandbox, timing};
+
+/// The variable a program's result is read from unless `ExecOptions::result_var` says otherwise.
+pub const RESULT_VAR: &str = "result";
+
+/// Characters of each variable's repr shown by `--dump-scope`.
+const SCOPE_REPR_LIMIT: usize = 200;
+
+#[derive(Debug)]
+pub enum ExecuteError {
+    CompileError(String),
+    ExecutionError(String),
+    /// The result variable, named here, wasn't set.
+    ResultNotFound(String),
+    /// The result variable, named here, was `None` with `strict`.
+    ResultNone(String),
+    ResultConversionError(String),
+    SchemaError(String),
+    SplitOutputError(String),
+    BlockedImport(String),
+    Timeout(Duration),
+    /// The program raised a warning with `strict`; holds the traceback.
+    Warning(String),
+}
+
+impl ExecuteError {
+    /// Whether `--strict-result` is what failed the run, as opposed to an error it would have had anyway.
+    pub fn is_strict_failure(&self) -> bool {
+        matches!(self, ExecuteError::ResultNotFound(_) | ExecuteError::ResultNone(_) | ExecuteError::Warning(_))
+    }
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecuteError::CompileError(err) =>
+                write!(f, "failed to compile Python program: {}", err),
+            ExecuteError::ExecutionError(err) =>
+                write!(f, "failed to execute Python program: {}", err),
+            ExecuteError::ResultNotFound(name) =>
+                write!(f, "'{}' variable not found", name),
+            ExecuteError::ResultNone(name) =>
+                write!(f, "'{}' variable is None", name),
+            ExecuteError::ResultConversionError(t) =>
+                write!(f, "can't convert the result of type {} to text; assign a string or use --json", t),
+            ExecuteError::SchemaError(err) =>
+                write!(f, "result does not match JSON schema: {}", err),
+            ExecuteError::SplitOutputError(err) =>
+                write!(f, "could not write split output: {}", err),
+            ExecuteError::BlockedImport(module) =>
+                write!(f, "import of '{}' is blocked in safe mode; allow it with --allow-module {}", module, module),
+            ExecuteError::Timeout(limit) =>
+                write!(f, "program did not finish within {}s; raise or disable the limit with --timeout (0 for none)", limit.as_secs()),
+            ExecuteError::Warning(traceback) =>
+                write!(f, "program raised a warning, which --strict-result treats as an error: {}", traceback),
+        }
+    }
+}
+
+/// How a program is run. The default runs it unsandboxed, without a time
+/// limit, and accepts printed output in place of `result`.
+#[derive(Default)]
+pub struct ExecOptions<'a> {
+    pub strict: bool,
+    /// Modules the program may import; `None` runs it unsandboxed.
+    pub sandbox: Option<&'a [String]>,
+    pub result_hook: Option<&'a str>,
+    /// The variable the result is read from; `None` for `result`.
+    pub result_var: Option<&'a str>,
+    /// Extra string variables set next to `data`, as name and value.
+    pub vars: &'a [(String, String)],
+    pub dump_scope: bool,
+    /// Where the program's print output goes; stderr if `None`.
+    pub stdout_path: Option<&'a Path>,
+    /// How long the program may run; `None` for no limit.
+    pub timeout: Option<Duration>,
+}
+
+impl ExecOptions<'_> {
+    /// The variable the result is read from.
+    pub fn result_var(&self) -> &str {
+        self.result_var.unwrap_or(RESULT_VAR)
+    }
+}
+
+/// Runs `program` on RustPython with `input` as `data` and returns its `result` as text. A
+/// sandboxed run or one with a time limit happens in a child process; see `isolate`.
+pub async fn execute_program(input: &str, program: &str, options: &ExecOptions<'_>) -> Result<String, ExecuteError> {
+    // A child process can be locked down, and killed once the time limit passes.
+    if options.sandbox.is_some() || options.timeout.is_some() {
+        return isolate::execute_program(input, program, options);
+    }
+    run_in_interpreter(input, program, options).emit(options.stdout_path)
+}
+
+/// What running a program produced, kept back until it's shown so it can be passed from a child
+/// process to gptxt.
+pub(crate) struct Outcome {
+    pub result: Result<String, ExecuteError>,
+    /// What the program printed, or `None` if it stood in for the result.
+    pub printed: Option<String>,
+    /// The program's variables, with `dump_scope`.
+    pub scope: Option<Vec<(String, String)>>,
+}
+
+impl Outcome {
+    /// Shows the program's output and scope, then returns its result.
+    pub fn emit(self, stdout_path: Option<&Path>) -> Result<String, ExecuteError> {
+        if let Some(printed) = &self.printed {
+            emit_program_stdout(printed, stdout_path);
+        }
+        if let Some(variables) = &self.scope {
+            print_scope(variables);
+        }
+        self.result
+    }
+}
+
+/// Runs `program` in a new interpreter. With a sandbox, this process is locked down for good
+/// before the program starts, so only a child process made for the run may ask for one.
+pub(crate) fn run_in_interpreter(input: &str, program: &str, options: &ExecOptions<'_>) -> Outcome {
+    let started = Instant::now();
+    let interp = rustpython::InterpreterConfig::new()
+        .init_stdlib()
+        .interpreter();
+    timing::record("interpreter init", started.elapsed());
+
+    let mut printed = None;
+    let mut scope_dump = None;
+    let result = interp.enter(|vm| {
+        let started = Instant::now();
+        let program_obj = vm.compile(program, vm::compiler::Mode::Exec, "<string>".to_owned());
+        timing::record("compilation", started.elapsed());
+        let program_obj = program_obj.map_err(|err| ExecuteError::CompileError(err.to_string()))?;
+
+        let hook_obj = match options.result_hook {
+            Some(hook) => Some(
+                vm.compile(hook, vm::compiler::Mode::Eval, "<result-hook>".to_owned())
+                    .map_err(|err| ExecuteError::CompileError(format!("result hook: {}", err)))?,
+            ),
+            None => None,
+        };
+
+        let scope = vm.new_scope_with_builtins();
+
+        // Set up before the sandbox, after which no more modules can be loaded from disk.
+        let capture_scope = vm.new_scope_with_builtins();
+        run_internal(vm, &capture_scope, "import sys, io\nsys.stdout = io.StringIO()\n", vm::compiler::Mode::Exec)?;
+        run_internal(vm, &capture_scope, RESULT_TO_TEXT, vm::compiler::Mode::Exec)?;
+        if options.strict {
+            run_internal(vm, &capture_scope, "import warnings\nwarnings.simplefilter('error')\n", vm::compiler::Mode::Exec)?;
+        }
+
+        if let Some(allowed) = options.sandbox {
+            run_internal(vm, &scope, &sandbox::prelude(allowed), vm::compiler::Mode::Exec)?;
+            sandbox::lock_down().map_err(|e| ExecuteError::ExecutionError(format!("could not start the sandbox: {}", e)))?;
+        }
+
+        let data_pyobj = vm.ctx.new_str(input);
+        scope
+            .locals
+            .set_item("data", PyObjectRef::from(data_pyobj), vm)
+            .expect("Failed to set variable in scope");
+        for (name, value) in options.vars {
+            scope
+                .locals
+                .set_item(name.as_str(), PyObjectRef::from(vm.ctx.new_str(value.as_str())), vm)
+                .expect("Failed to set variable in scope");
+        }
+
+        let started = Instant::now();
+        let run = vm.run_code_obj(program_obj, scope.clone());
+        timing::record("execution", started.elapsed());
+        let captured = run_internal(vm, &capture_scope, "sys.stdout.getvalue()", vm::compiler::Mode::Eval)?;
+        let captured = captured.try_into_value::<String>(vm).unwrap_or_default();
+        let result_pyobj = scope.locals.get_item(options.result_var(), vm).ok();
+
+        let stdout_is_result = run.is_ok() && result_pyobj.is_none() && stdout_can_be_result(&captured, options);
+        if options.dump_scope {
+            scope_dump = Some(scope_variables(vm, &scope));
+        }
+        if stdout_is_result {
+            return Ok(stdout_as_result(&captured));
+        }
+        printed = Some(captured);
+        run.map_err(|err| match execution_error(vm, &err) {
+            // Only `strict` turns warnings into exceptions.
+            ExecuteError::ExecutionError(traceback) if options.strict && err.class().name().ends_with("Warning") => {
+                ExecuteError::Warning(traceback)
+            }
+            e => e,
+        })?;
+
+        let mut result_pyobj =
+            result_pyobj.ok_or_else(|| ExecuteError::ResultNotFound(options.result_var().to_owned()))?;
+
+        if let Some(hook_obj) = hook_obj {
+            result_pyobj = vm
+                .run_code_obj(hook_obj, scope.clone())
+                .map_err(|err| execution_error(vm, &err))?;
+        }
+
+        if options.strict && vm.is_none(&result_pyobj) {
+            return Err(ExecuteError::ResultNone(options.result_var().to_owned()));
+        }
+
+        if let Ok(result) = result_pyobj.clone().try_into_value::<String>(vm) {
+            return Ok(result);
+        }
+        let type_name = result_pyobj.class().name().to_owned();
+        capture_scope
+            .locals
+            .set_item("value", result_pyobj, vm)
+            .expect("Failed to set variable in scope");
+        run_internal(vm, &capture_scope, "__gptxt_to_text(value)", vm::compiler::Mode::Eval)
+            .ok()
+            .and_then(|text| text.try_into_value::<String>(vm).ok())
+            .ok_or(ExecuteError::ResultConversionError(type_name))
+    });
+
+    Outcome { result, printed, scope: scope_dump }
+}
+
+/// Converts a non-string `result` to text: bytes are decoded as UTF-8,
+/// numbers and bools go through `str()`, lists and tuples become one item per line, and dicts
+/// become JSON. Anything else raises `TypeError`.
+pub(crate) const RESULT_TO_TEXT: &str = r#"import json as __gptxt_json
+
+def __gptxt_to_text(value):
+    if isinstance(value, str):
+        return value
+    if isinstance(value, (bytes, bytearray)):
+        return bytes(value).decode('utf-8')
+    if isinstance(value, (bool, int, float)):
+        return str(value)
+    if isinstance(value, (list, tuple)):
+        return '\n'.join(item.decode('utf-8') if isinstance(item, bytes) else str(item) for item in value)
+    if isinstance(value, dict):
+        return __gptxt_json.dumps(value, default=str)
+    raise TypeError(type(value).__name__)
+"#;
+
+/// Runs a snippet of gptxt's own Python, as opposed to the program's, in `scope`.
+fn run_internal(
+    vm: &vm::VirtualMachine,
+    scope: &vm::scope::Scope,
+    source: &str,
+    mode: vm::compiler::Mode,
+) -> Result<PyObjectRef, ExecuteError> {
+    let code = vm
+        .compile(source, mode, "<gptxt>".to_owned())
+        .map_err(|err| ExecuteError::CompileError(err.to_string()))?;
+    vm.run_code_obj(code, scope.clone())
+        .map_err(|err| execution_error(vm, &err))
+}
+
+/// Programs often print their output instead of assigning `result`. When they do, the printed
+/// output stands in for `result`, unless `--strict-result` asks for `result` itself.
+pub(crate) fn stdout_can_be_result(output: &str, options: &ExecOptions) -> bool {
+    !output.is_empty() && !options.strict
+}
+
+/// Printed output used as the result, minus the line ending of the last `print`.
+pub(crate) fn stdout_as_result(output: &str) -> String {
+    let output = output.strip_suffix('\n').unwrap_or(output);
+    output.strip_suffix('\r').unwrap_or(output).to_owned()
+}
+
+/// Sends a program's print output to `--separate-stdout`, or stderr by default, so stdout only
+/// ever carries `result`.
+pub fn emit_program_stdout(output: &str, path: Option<&Path>) {
+    match path {
+        Some(path) => {
+            if let Err(e) = fs::write(path, output) {
+                print_warn!("could not write program output to {}: {}", path.display(), e);
+            }
+        }
+        None if output.is_empty() => {}
+        None => {
+            eprint!("{}", output);
+            if !output.ends_with('\n') {
+                eprintln!();
+            }
+        }
+    }
+}
+
+/// Names and reprs of the variables a program defined, skipping dunders and imported modules.
+fn scope_variables(vm: &vm::VirtualMachine, scope: &vm::scope::Scope) -> Vec<(String, String)> {
+    let mut variables = Vec::new();
+    for (key, value) in &scope.globals {
+        let Ok(name) = key.str(vm) else { continue };
+        let name = name.as_str().to_owned();
+        if (name.starts_with("__") && name.ends_with("__")) || value.class().name().to_owned() == "module" {
+            continue;
+        }
+        let repr = match value.repr(vm) {
+            Ok(repr) => repr.as_str().to_owned(),
+            Err(_) => format!("<unrepresentable {}>", value.class().name().to_owned()),
+        };
+        variables.push((name, repr));
+    }
+    variables
+}
+
+/// Prints `--dump-scope` variables where prompts go (see `util::ui`), truncating long reprs.
+pub(crate) fn print_scope(variables: &[(String, String)]) {
+    print_progress!("scope:");
+    ui_println!("------------------------------");
+    for (name, repr) in variables {
+        if repr.chars().count() > SCOPE_REPR_LIMIT {
+            let truncated: String = repr.chars().take(SCOPE_REPR_LIMIT).collect();
+            ui_println!("{} = {}... ({} chars)", name, truncated, repr.chars().count());
+        } else {
+            ui_println!("{} = {}", name, repr);
+        }
+    }
+    ui_println!("------------------------------");
+}
+
+fn execution_error(vm: &vm::VirtualMachine, err: &vm::builtins::PyBaseExceptionRef) -> ExecuteError {
+    // Raised by the sandbox prelude's import hook.
+    if err.class().name().to_owned() == "BlockedImportError" {
+        if let Some(module) = err.args().as_slice().first().and_then(|m| m.str(vm).ok()) {
+            return ExecuteError::BlockedImport(module.as_str().to_owned());
+        }
+    }
+
+    let mut buf = String::new();
+    vm.write_exception(&mut buf, err)
+        .expect("Failed to write exception");
+    ExecuteError::ExecutionError(buf)
+}
@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::execute::{ExecOptions, RESULT_TO_TEXT};
+use crate::util::{self, LineEnding};
+
+/// Ends the heredoc that carries the Python script in an exported shell script.
+const HEREDOC_END: &str = "GPTXT_PROGRAM_END";
+
+/// A Python script that runs `program` on its own: `data` comes from the file named by its first
+/// argument, or STDIN, and the result is read and written the way gptxt would. A short harness
+/// comes first and the program follows it as written, then a single line that writes the result.
+///
+/// Only `strict`, `result_hook`, `result_var` and `vars` are taken from `options`; the script is
+/// never sandboxed or time-limited, and what the program prints goes to stderr.
+pub fn standalone_python(
+    task: &str,
+    program: &str,
+    options: &ExecOptions<'_>,
+    line_endings: LineEnding,
+    split_output: Option<&Path>,
+) -> String {
+    // A JSON string is also a valid Python string literal, and likewise a JSON object of strings
+    // is a valid dict literal.
+    let literal = |s: &str| serde_json::to_string(s).expect("a string always serializes");
+    let vars = serde_json::Value::Object(
+        options.vars.iter().map(|(name, value)| (name.clone(), value.as_str().into())).collect(),
+    );
+    let line_ending = match line_endings {
+        LineEnding::Keep => "None",
+        LineEnding::Lf => "'\\n'",
+        LineEnding::Crlf => "'\\r\\n'",
+    };
+    let python_bool = |b: bool| if b { "True" } else { "False" };
+
+    // Indented to sit in `__gptxt_finish`.
+    let write_result = match split_output {
+        Some(dir) => format!(
+            r#"import os as __gptxt_os
+    files = __gptxt_json.loads(result)
+    for name, contents in files.items():
+        if __gptxt_os.path.isabs(name) or '..' in name.split(__gptxt_os.sep):
+            __gptxt_sys.exit('invalid file name: %r' % name)
+        if not isinstance(contents, str):
+            __gptxt_sys.exit('the contents of %r are a %s, not a str' % (name, type(contents).__name__))
+    for name, contents in files.items():
+        path = __gptxt_os.path.join({dir}, name)
+        __gptxt_os.makedirs(__gptxt_os.path.dirname(path) or '.', exist_ok=True)
+        with open(path, 'w', newline='') as f:
+            f.write(__gptxt_normalize(contents))"#,
+            dir = literal(&dir.display().to_string()),
+        ),
+        None => "__gptxt_stdout.write(__gptxt_normalize(result) + (__GPTXT_LINE_ENDING or '\\n'))".to_owned(),
+    };
+
+    let mut program = program.to_owned();
+    if !program.ends_with('\n') {
+        program.push('\n');
+    }
+
+    format!(
+        r#"#!/usr/bin/env python3
+# Generated by gptxt for the task: {task}
+# gptxt's harness; the program itself starts after the line of dashes.
+import atexit as __gptxt_atexit, io as __gptxt_io, sys as __gptxt_sys
+{result_to_text}
+__GPTXT_RESULT_VAR = {result_var}
+__GPTXT_RESULT_HOOK = {result_hook}
+__GPTXT_STRICT = {strict}
+__GPTXT_LINE_ENDING = {line_ending}
+__gptxt_done = []
+
+def __gptxt_normalize(text):
+    if __GPTXT_LINE_ENDING is None:
+        return text
+    return text.replace('\r\n', '\n').replace('\n', __GPTXT_LINE_ENDING)
+
+@__gptxt_atexit.register
+def __gptxt_show_printed():
+    # If the program failed, what it printed still goes to stderr.
+    if not __gptxt_done:
+        __gptxt_sys.stderr.write(__gptxt_printed.getvalue())
+
+def __gptxt_finish(scope):
+    __gptxt_done.append(True)
+    printed = __gptxt_printed.getvalue()
+    # Printed output stands in for a missing result, as in gptxt.
+    if __GPTXT_RESULT_VAR not in scope and printed and not __GPTXT_STRICT:
+        result = printed[:-1] if printed.endswith('\n') else printed
+        result = result[:-1] if result.endswith('\r') else result
+    else:
+        __gptxt_sys.stderr.write(printed if not printed or printed.endswith('\n') else printed + '\n')
+        if __GPTXT_RESULT_VAR not in scope:
+            __gptxt_sys.exit("'%s' variable not found" % __GPTXT_RESULT_VAR)
+        result = scope[__GPTXT_RESULT_VAR]
+        if __GPTXT_RESULT_HOOK is not None:
+            result = eval(__GPTXT_RESULT_HOOK, scope)
+        if __GPTXT_STRICT and result is None:
+            __gptxt_sys.exit("'%s' variable is None" % __GPTXT_RESULT_VAR)
+        try:
+            result = __gptxt_to_text(result)
+        except TypeError:
+            __gptxt_sys.exit("can't convert the result of type %s to text" % type(result).__name__)
+    __gptxt_stdout.reconfigure(newline='')
+    {write_result}
+
+if len(__gptxt_sys.argv) > 1:
+    with open(__gptxt_sys.argv[1], newline='') as __gptxt_f:
+        data = __gptxt_f.read()
+else:
+    data = __gptxt_sys.stdin.read()
+globals().update({vars})
+# The program may read STDIN itself instead of using `data`.
+__gptxt_sys.stdin = __gptxt_io.StringIO(data)
+__gptxt_stdout = __gptxt_sys.stdout
+__gptxt_printed = __gptxt_sys.stdout = __gptxt_io.StringIO()
+{strict_warnings}
+# ----------------------------------------------------------------------------------------------
+{program}
+__gptxt_finish(globals())
+"#,
+        task = task.replace('\n', " "),
+        result_to_text = RESULT_TO_TEXT,
+        result_var = literal(options.result_var()),
+        result_hook = options.result_hook.map_or_else(|| "None".to_owned(), literal),
+        strict = python_bool(options.strict),
+        strict_warnings = if options.strict { "import warnings as __gptxt_warnings\n__gptxt_warnings.simplefilter('error')" } else { "" },
+    )
+}
+
+/// A `/bin/sh` script that runs `python` on the recorded input file (overridable with the
+/// script's first argument, or STDIN if there was none) and sends the result to `output`.
+pub fn shell_script(task: &str, python: &str, input_file: Option<&str>, output: Option<&Path>) -> Result<String, String> {
+    if python.lines().any(|line| line == HEREDOC_END) {
+        return Err(format!("the program contains the line '{}'", HEREDOC_END));
+    }
+
+    let (input_default, input) = match input_file {
+        Some(file) => (format!("input=$1\n[ -n \"$input\" ] || input={}\n\n", util::shell_quote(file)), " \"$input\""),
+        None => (String::new(), " \"$@\""),
+    };
+    let output = match output {
+        Some(path) => format!(" > {}", util::shell_quote(&path.display().to_string())),
+        None => String::new(),
+    };
+
+    Ok(format!(
+        r#"#!/bin/sh
+# Generated by gptxt for the task: {task}
+# Usage: {usage}
+set -e
+
+program=$(cat <<'{end}'
+{python}
+{end}
+)
+
+{input_default}exec "${{PYTHON:-python3}}" -c "$program"{input}{output}
+"#,
+        task = task.replace('\n', " "),
+        usage = match input_file {
+            Some(file) => format!("$0 [input-file]  (defaults to {})", file),
+            None => "$0 [input-file]  (reads STDIN if no file is given)".to_owned(),
+        },
+        end = HEREDOC_END,
+        python = python.trim_end(),
+    ))
+}
+
+/// Writes `contents` to `path` and makes it executable.
+pub fn write_executable(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    fs::write(path, contents)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
@@ -0,0 +1,516 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use openai::chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole};
+use openai::Usage;
+use serde_json::json;
+
+use crate::{keys, sample, timing};
+
+/// What `generate_program` asks for, and how: the part of the command line that shapes the prompt
+/// and the request.
+#[derive(Clone, Debug)]
+pub struct GenerateOptions {
+    pub task: String,
+    pub temperature: f32,
+    pub max_tokens: u16,
+    /// Used instead of `SYSTEM_MESSAGE`.
+    pub system_message: Option<String>,
+    /// Added to the end of the system message, each line as a comment.
+    pub instructions: Vec<String>,
+    pub no_system_stdin_line: bool,
+    pub show_lines: Option<u16>,
+    pub squash_blank_lines: bool,
+    pub show_head_tail: Option<(u16, u16)>,
+    pub tail_lines: Option<u16>,
+    pub sample_lines: Option<u16>,
+    /// Picks the `sample_lines` lines.
+    pub seed: u64,
+    pub jsonify: bool,
+    pub jsonify_one_line: bool,
+    /// Asks for `result` to be a dict of file names to contents.
+    pub split_output: bool,
+    /// The variable the program is told to store its result in, usually `result`.
+    pub result_var: String,
+    /// Names of the extra string variables the program will find next to `data`.
+    pub vars: Vec<String>,
+    /// A file whose contents stand in for the API response.
+    pub fixture: Option<PathBuf>,
+    /// A file each API call is appended to as a JSON line.
+    pub trace: Option<PathBuf>,
+    /// Hides the notice when a rate-limited key is switched for another; the switch still happens.
+    pub quiet: bool,
+}
+
+pub const MIN_TEMPERATURE: f32 = 0.05;
+pub const MAX_TEMPERATURE: f32 = 1.0;
+
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// `--max-tokens` values below this get a warning that programs may be truncated.
+const LOW_MAX_TOKENS: u16 = 128;
+
+/// Model used when neither `--model` nor the `models` or `model` config key is set.
+pub const MODEL: &str = "gpt-3.5-turbo";
+
+/// Known completion token limits; `--max-tokens` is capped to these. Unknown models are left alone.
+const MODEL_OUTPUT_LIMITS: &[(&str, u16)] = &[
+    ("gpt-3.5-turbo", 4096),
+    ("gpt-4", 8192),
+    ("gpt-4-turbo", 4096),
+    ("gpt-4o", 16384),
+    ("gpt-4o-mini", 16384),
+];
+
+fn model_output_limit(model: &str) -> Option<u16> {
+    MODEL_OUTPUT_LIMITS
+        .iter()
+        .find(|(name, _)| *name == model)
+        .map(|(_, limit)| *limit)
+}
+
+pub const SYSTEM_MESSAGE: &str = "# You are part of a tool that creates Python code for text processing.
+# You should return only Python code with no comments.
+# Do not describe the code or add any additional information about the code.
+# Data to process is stored in the string variable `data`.
+# Results should be stored in the variable `result`.
+";
+
+const STDIN_PREAMBLE: &str = "
+import sys
+data = sys.stdin.read()
+";
+
+/// The system message (built-in or `--system-file`) followed by any added instructions. These
+/// are turned into comments line by line, so they can't add code ahead of the stdin preamble. The
+/// built-in message names `result_var`; a custom one is used as written.
+pub fn system_message(options: &GenerateOptions) -> String {
+    let mut message = match &options.system_message {
+        Some(message) => message.clone(),
+        None => SYSTEM_MESSAGE.replace("`result`", &format!("`{}`", options.result_var)),
+    };
+    if !message.is_empty() && !message.ends_with('\n') {
+        message.push('\n');
+    }
+    for line in options.instructions.iter().flat_map(|text| text.lines()).map(str::trim) {
+        match line {
+            "" => {}
+            _ if line.starts_with('#') => message.push_str(&format!("{}\n", line)),
+            _ => message.push_str(&format!("# {}\n", line)),
+        }
+    }
+    message
+}
+
+/// Appends one JSON line per API call. The API key is never part of the entry.
+fn trace_request(
+    path: &Path,
+    model: &str,
+    temperature: f32,
+    max_tokens: u16,
+    messages: &[ChatCompletionMessage],
+    response: serde_json::Value,
+    started: Instant,
+) {
+    let entry = json!({
+        "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        "request": {
+            "model": model,
+            "temperature": temperature,
+            "max_tokens": max_tokens,
+            "messages": messages,
+        },
+        "response": response,
+        "latency_ms": started.elapsed().as_millis() as u64,
+    });
+    if let Err(e) = append_trace(path, &entry) {
+        print_warn!("could not write trace to {}: {}", path.display(), e);
+    }
+}
+
+fn append_trace(path: &Path, entry: &serde_json::Value) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry)
+}
+
+/// First line of what `apply_output_wrappers` appends.
+pub const OUTPUT_WRAPPER_IMPORT: &str = "import json as __gptxt_json\n";
+
+/// Appends the lines that turn the result variable into the requested output format.
+pub fn apply_output_wrappers(options: &GenerateOptions, program: &str) -> String {
+    let var = &options.result_var;
+    let wrapper = if options.jsonify_one_line {
+        format!("{var} = __gptxt_json.dumps({var}, separators=(',', ':'))")
+    } else if options.jsonify || options.split_output {
+        // File contents aren't coerced to text; anything but a str is rejected once it's back.
+        format!("{var} = __gptxt_json.dumps({var})")
+    } else {
+        return program.to_owned();
+    };
+
+    // The wrapper always starts on a fresh line, and its aliased import can't clash with (or be
+    // shadowed by) a `json` the program imported or assigned itself.
+    let separator = if program.is_empty() || program.ends_with('\n') { "" } else { "\n" };
+    format!("{}{}{}{}\n", program, separator, OUTPUT_WRAPPER_IMPORT, wrapper)
+}
+
+/// A program that failed when run, and why.
+pub struct Failure<'a> {
+    pub program: &'a str,
+    pub error: &'a str,
+}
+
+pub struct Generation {
+    /// The messages sent, as shown by `--show-prompt`.
+    pub prompt: String,
+    pub program: String,
+    /// `None` for fixtures and programs written by hand.
+    pub usage: Option<Usage>,
+}
+
+/// Builds the messages sent to the model: the system message, then a user message with any input
+/// sample and the task.
+pub fn assemble_messages(options: &GenerateOptions, input: &str, failure: Option<&Failure>) -> Vec<ChatCompletionMessage> {
+    let mut system = system_message(options);
+    if !options.no_system_stdin_line {
+        system.push_str(STDIN_PREAMBLE);
+    }
+
+    let mut prompt = String::new();
+    if let Some(n) = options.show_lines {
+        let shown_lines = head(input, n as usize, options.squash_blank_lines)
+            .iter()
+            .map(|s| format!("#>{}", s))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        prompt.push_str(&format!(
+            "\n# First {} lines of `data`:\n{}\n",
+            n, shown_lines
+        ));
+    }
+
+    if let Some((head, tail)) = options.show_head_tail {
+        let lines = input.lines().collect::<Vec<&str>>();
+        let (head, tail) = (head as usize, tail as usize);
+        let quote = |lines: &[&str]| {
+            lines
+                .iter()
+                .map(|s| format!("#>{}", s))
+                .collect::<Vec<String>>()
+                .join("\n")
+        };
+
+        if head + tail >= lines.len() {
+            // The two ends overlap, so the whole input fits in the sample.
+            prompt.push_str(&format!(
+                "\n# All {} lines of `data`:\n{}\n",
+                lines.len(), quote(&lines)
+            ));
+        } else {
+            prompt.push_str(&format!(
+                "\n# First {} and last {} lines of `data`:\n{}\n# ... ({} lines omitted) ...\n{}\n",
+                head, tail,
+                quote(&lines[..head]),
+                lines.len() - head - tail,
+                quote(&lines[lines.len() - tail..])
+            ));
+        }
+    }
+
+    let quote = |lines: Vec<&str>| lines.iter().map(|s| format!("#>{}", s)).collect::<Vec<String>>().join("\n");
+    if let Some(n) = options.tail_lines {
+        let shown = sample::tail(input, n as usize);
+        let header = if shown.len() < input.lines().count() { "Last" } else { "All" };
+        prompt.push_str(&format!("\n# {} {} lines of `data`:\n{}\n", header, shown.len(), quote(shown)));
+    }
+    if let Some(n) = options.sample_lines {
+        let shown = sample::random(input, n as usize, options.seed);
+        let total = input.lines().count();
+        if shown.len() < total {
+            prompt.push_str(&format!(
+                "\n# {} of the {} lines of `data`, picked at random and in order:\n{}\n",
+                shown.len(), total, quote(shown)
+            ));
+        } else {
+            prompt.push_str(&format!("\n# All {} lines of `data`:\n{}\n", total, quote(shown)));
+        }
+    }
+
+    if !options.vars.is_empty() {
+        let names = options.vars.iter().map(|name| format!("`{}`", name)).collect::<Vec<String>>();
+        prompt.push_str(&format!("\n# These string variables are also set and can be used: {}\n", names.join(", ")));
+    }
+
+    if options.split_output {
+        prompt.push_str(&format!(
+            "\n# `{}` should be a dict mapping output file names to their contents.\n",
+            options.result_var
+        ));
+    }
+
+    prompt.push_str(&format!("\n# {}:", options.task));
+
+    let mut messages = vec![
+        chat_message(ChatCompletionMessageRole::System, &system),
+        chat_message(ChatCompletionMessageRole::User, prompt.trim_start()),
+    ];
+
+    if let Some(failure) = failure {
+        // Without the output wrappers, which are added again to whatever comes back.
+        let own_code = failure.program.split(OUTPUT_WRAPPER_IMPORT).next().unwrap_or(failure.program);
+        messages.push(chat_message(ChatCompletionMessageRole::Assistant, own_code.trim_end()));
+        messages.push(chat_message(
+            ChatCompletionMessageRole::User,
+            &format!("# The previous program failed with:\n{}\n# Fix it and return the whole program.", failure.error.trim_end()),
+        ));
+    }
+    messages
+}
+
+/// The first `n` lines of `input`. With `squash`, runs of blank lines count as one; only the
+/// sample is squashed, and the program still sees every blank line in `data`.
+fn head(input: &str, n: usize, squash: bool) -> Vec<&str> {
+    let mut previous_blank = false;
+    input
+        .lines()
+        .filter(|line| {
+            let blank = line.trim().is_empty();
+            let squashed = squash && blank && previous_blank;
+            previous_blank = blank;
+            !squashed
+        })
+        .take(n)
+        .collect()
+}
+
+/// The lines of `input` the prompt shows the model, in order, or `None` if it shows none.
+pub fn prompt_sample<'a>(options: &GenerateOptions, input: &'a str) -> Option<Vec<&'a str>> {
+    if let Some(n) = options.show_lines {
+        return Some(head(input, n as usize, options.squash_blank_lines));
+    }
+    if let Some((head, tail)) = options.show_head_tail {
+        let lines = input.lines().collect::<Vec<&str>>();
+        let (head, tail) = (head as usize, tail as usize);
+        if head + tail >= lines.len() {
+            return Some(lines);
+        }
+        return Some([&lines[..head], &lines[lines.len() - tail..]].concat());
+    }
+    if let Some(n) = options.tail_lines {
+        return Some(sample::tail(input, n as usize));
+    }
+    options.sample_lines.map(|n| sample::random(input, n as usize, options.seed))
+}
+
+fn chat_message(role: ChatCompletionMessageRole, content: &str) -> ChatCompletionMessage {
+    ChatCompletionMessage {
+        role,
+        content: content.to_owned(),
+        name: None,
+    }
+}
+
+/// Renders `messages` as text for `--show-prompt`, bundles, and traces, one `[role]` block each.
+pub fn render_messages(messages: &[ChatCompletionMessage]) -> String {
+    messages
+        .iter()
+        .map(|m| format!("[{}]\n{}", role_name(&m.role), m.content.trim_end()))
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+pub fn role_name(role: &ChatCompletionMessageRole) -> &'static str {
+    match role {
+        ChatCompletionMessageRole::System => "system",
+        ChatCompletionMessageRole::User => "user",
+        ChatCompletionMessageRole::Assistant => "assistant",
+    }
+}
+
+/// Chat models tend to wrap code in a Markdown fence despite the system message; the fence isn't
+/// part of the program.
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("```") else { return text };
+    let Some(body) = rest.strip_suffix("```") else { return text };
+    // Drop the language tag, if any, that follows the opening fence.
+    match body.split_once('\n') {
+        Some((_, code)) => code.trim(),
+        None => text,
+    }
+}
+
+pub async fn generate_program(
+    options: &GenerateOptions,
+    model: &str,
+    input: &str,
+    failure: Option<&Failure<'_>>,
+) -> Result<Generation, Box<dyn Error>> {
+    let messages = assemble_messages(options, input, failure);
+    let prompt = render_messages(&messages);
+
+    let max_tokens = match model_output_limit(model) {
+        Some(limit) if options.max_tokens > limit => {
+            // Once per run, not on every regeneration.
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| {
+                print_warn!("--max-tokens {} exceeds the {} limit for {}; using {}", options.max_tokens, limit, model, limit);
+            });
+            limit
+        }
+        _ => options.max_tokens,
+    };
+
+    // Fixtures stand in for the API response only; everything around it runs as usual.
+    if let Some(path) = &options.fixture {
+        let program = fs::read_to_string(path)
+            .map_err(|e| format!("could not read fixture {}: {}", path.display(), e))?;
+        return Ok(Generation {
+            prompt,
+            program: apply_output_wrappers(options, program.trim()),
+            usage: None,
+        });
+    }
+
+    if max_tokens < LOW_MAX_TOKENS {
+        static WARNED: Once = Once::new();
+        WARNED.call_once(|| {
+            print_warn!(
+                "--max-tokens {} is low; programs for non-trivial tasks may be cut off (try 256 or more)",
+                max_tokens
+            );
+        });
+    }
+
+    let started = Instant::now();
+    let completion = create_chat_completion(model, &messages, options.temperature, max_tokens, options.quiet).await;
+    timing::record("API request", started.elapsed());
+
+    if let Some(path) = &options.trace {
+        let response = match &completion {
+            Ok(Ok(c)) => json!({
+                "text": c.choices.first().map(|choice| choice.message.content.clone()),
+                "usage": c.usage.as_ref().map(|u| json!({
+                    "prompt_tokens": u.prompt_tokens,
+                    "completion_tokens": u.completion_tokens,
+                    "total_tokens": u.total_tokens,
+                })),
+            }),
+            Ok(Err(e)) => json!({ "error": e.to_string() }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        trace_request(path, model, options.temperature, max_tokens, &messages, response, started);
+    }
+
+    let completion = completion?;
+
+    match completion {
+        Ok(completion_result) => {
+            let program = strip_code_fence(&completion_result.choices.first().unwrap().message.content);
+
+            let program = apply_output_wrappers(options, program);
+            Ok(Generation {
+                prompt,
+                program,
+                usage: completion_result.usage,
+            })
+        }
+        Err(error) => Err(Box::new(error)),
+    }
+}
+
+/// Sends a free-form prompt at low temperature and returns the trimmed completion text.
+pub async fn complete_text(
+    trace: Option<&Path>,
+    model: &str,
+    prompt: &str,
+    max_tokens: u16,
+) -> Result<String, Box<dyn Error>> {
+    let messages = vec![chat_message(ChatCompletionMessageRole::User, prompt)];
+    let started = Instant::now();
+    let completion = create_chat_completion(model, &messages, MIN_TEMPERATURE, max_tokens, false).await;
+    timing::record("API request", started.elapsed());
+
+    if let Some(path) = trace {
+        let response = match &completion {
+            Ok(Ok(c)) => json!({ "text": c.choices.first().map(|choice| choice.message.content.clone()) }),
+            Ok(Err(e)) => json!({ "error": e.to_string() }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        trace_request(path, model, MIN_TEMPERATURE, max_tokens, &messages, response, started);
+    }
+
+    match completion? {
+        Ok(completion) => Ok(completion
+            .choices
+            .first()
+            .map(|choice| choice.message.content.trim().to_owned())
+            .unwrap_or_default()),
+        Err(error) => Err(Box::new(error)),
+    }
+}
+
+/// Sends a chat completion request, switching to the next configured API key and retrying when
+/// the current one is rate limited.
+async fn create_chat_completion(
+    model: &str,
+    messages: &[ChatCompletionMessage],
+    temperature: f32,
+    max_tokens: u16,
+    quiet: bool,
+) -> openai::ApiResponseOrError<ChatCompletion> {
+    loop {
+        let completion = ChatCompletion::builder(model, messages.to_vec())
+            .temperature(temperature)
+            .max_tokens(max_tokens)
+            .create()
+            .await;
+
+        match &completion {
+            Ok(Err(e)) if is_rate_limit_error(e) && keys::rotate_after_rate_limit() => {
+                if quiet {
+                    continue;
+                }
+                print_warn!("API key rate limited; switching to key {}", keys::current_label().unwrap_or_default());
+            }
+            _ => return completion,
+        }
+    }
+}
+
+fn is_rate_limit_error(e: &openai::OpenAiError) -> bool {
+    e.code.as_deref() == Some("rate_limit_exceeded") || e.message.contains("Rate limit")
+}
+
+/// Rate limits, server errors, and dropped or timed-out connections may go away if the request is
+/// sent again; anything else, like a bad key or an invalid request, fails the same way every time.
+pub fn is_retryable_api_error(e: &(dyn Error + 'static)) -> bool {
+    if let Some(e) = e.downcast_ref::<openai::OpenAiError>() {
+        return is_rate_limit_error(e) || e.message.contains("server had an error") || e.message.contains("overloaded");
+    }
+    match e.downcast_ref::<reqwest::Error>() {
+        Some(e) => e.is_timeout() || e.is_connect() || e.status().is_some_and(|s| s.is_server_error()),
+        None => false,
+    }
+}
+
+/// Doubles from `RETRY_BASE_DELAY` with each attempt after the first, up to `RETRY_MAX_DELAY`.
+pub fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .checked_mul(1 << attempt.saturating_sub(1).min(16))
+        .map_or(RETRY_MAX_DELAY, |delay| delay.min(RETRY_MAX_DELAY))
+}
+
+/// Authentication errors fail the same way for every model, so there's no point trying the next one.
+pub fn is_fatal_api_error(e: &(dyn Error + 'static)) -> bool {
+    match e.downcast_ref::<openai::OpenAiError>() {
+        Some(e) => e.code.as_deref() == Some("invalid_api_key") || e.message.contains("API key"),
+        None => false,
+    }
+}
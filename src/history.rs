@@ -0,0 +1,67 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use toml::Value;
+
+/// Programs previously generated for a (task, model) pair, persisted across sessions.
+pub struct ProgramHistory {
+    path: PathBuf,
+    programs: Vec<String>,
+}
+
+impl ProgramHistory {
+    pub fn load(task: &str, model: &str) -> Result<ProgramHistory, Box<dyn Error>> {
+        let data_dir = dirs::data_dir().ok_or("Unable to find data directory")?;
+        let history_dir = data_dir.join("gptxt").join("history");
+
+        if !history_dir.exists() {
+            fs::create_dir_all(&history_dir)?;
+        }
+
+        let path = history_dir.join(format!("{:016x}.toml", fnv1a(&format!("{}\0{}", model, task))));
+
+        let mut programs = Vec::new();
+        if path.exists() {
+            let history = fs::read_to_string(&path)?.parse::<Value>()?;
+            if let Some(Value::Array(entries)) = history.get("programs") {
+                programs = entries
+                    .iter()
+                    .filter_map(|v| v.as_str().map(|s| s.to_owned()))
+                    .collect();
+            }
+        }
+
+        Ok(ProgramHistory { path, programs })
+    }
+
+    pub fn contains(&self, program: &str) -> bool {
+        self.programs.iter().any(|p| p == program)
+    }
+
+    pub fn push(&mut self, program: &str) -> Result<(), Box<dyn Error>> {
+        if self.contains(program) {
+            return Ok(());
+        }
+        self.programs.push(program.to_owned());
+
+        let mut table = toml::map::Map::new();
+        table.insert(
+            "programs".to_owned(),
+            Value::Array(self.programs.iter().cloned().map(Value::String).collect()),
+        );
+        fs::write(&self.path, toml::to_string(&Value::Table(table))?)?;
+
+        Ok(())
+    }
+}
+
+// Stable across Rust releases, unlike `DefaultHasher`, so history files stay addressable.
+pub fn fnv1a(s: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
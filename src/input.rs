@@ -0,0 +1,84 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+use flate2::bufread::GzDecoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Reads the input file at `path`, decompressing it if `gzip` is set or it looks gzipped, and
+/// holding it to `max_bytes` (see `read_limited`).
+pub fn read_file(path: &Path, max_bytes: Option<u64>, truncate: bool, gzip: bool) -> io::Result<String> {
+    let f = File::open(path)?;
+    let size = f.metadata().ok().map(|m| m.len());
+    let (reader, gzipped) = decompressed(io::BufReader::new(f), gzip)?;
+    // The file size says nothing about how large the decompressed input is.
+    read_limited(reader, max_bytes, truncate, if gzipped { None } else { size })
+}
+
+/// Reads STDIN the way `read_file` reads a file.
+pub fn read_stdin(max_bytes: Option<u64>, truncate: bool, gzip: bool) -> io::Result<String> {
+    let stdin = io::stdin();
+    let (reader, _) = decompressed(stdin.lock(), gzip)?;
+    read_limited(reader, max_bytes, truncate, None)
+}
+
+/// Returns `reader`, decompressed if `gzip` is set or the input starts with the gzip magic bytes,
+/// and whether it was decompressed.
+fn decompressed<'a, R: BufRead + 'a>(mut reader: R, gzip: bool) -> io::Result<(Box<dyn Read + 'a>, bool)> {
+    if gzip || reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok((Box::new(GzipInput(GzDecoder::new(reader))), true))
+    } else {
+        Ok((Box::new(reader), false))
+    }
+}
+
+/// Reports any failure to decompress as an `InvalidInput` error, so it's fatal like an oversized
+/// input rather than being read as empty input.
+struct GzipInput<R>(GzDecoder<R>);
+
+impl<R: BufRead> Read for GzipInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0
+            .read(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid gzip input: {}", e)))
+    }
+}
+
+/// Reads at most `max_bytes` (plus one, to detect overflow) so oversized input is never fully
+/// buffered. Exceeding the limit is an `InvalidInput` error unless `truncate` is set.
+fn read_limited<R: Read>(
+    mut reader: R,
+    max_bytes: Option<u64>,
+    truncate: bool,
+    size: Option<u64>,
+) -> io::Result<String> {
+    let Some(max) = max_bytes else {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        return Ok(input);
+    };
+
+    let mut buf = Vec::new();
+    reader.take(max.saturating_add(1)).read_to_end(&mut buf)?;
+
+    if buf.len() as u64 > max {
+        let described = match size {
+            Some(size) => format!("input is {} bytes, exceeding --max-input-bytes {}", size, max),
+            None => format!("input exceeds --max-input-bytes {}", max),
+        };
+        if !truncate {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, described));
+        }
+        print_warn!("{}; truncating", described);
+        buf.truncate(max as usize);
+        // Don't leave half of a multi-byte character at the cut.
+        if let Err(e) = std::str::from_utf8(&buf) {
+            if e.error_len().is_none() {
+                buf.truncate(e.valid_up_to());
+            }
+        }
+    }
+
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,176 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, ErrorKind, Read, Write};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use crate::execute::{self, ExecOptions, ExecuteError, Outcome};
+use crate::{timing, util};
+
+/// The argument gptxt is run again with to execute one program in a child process.
+pub const CHILD_ARG: &str = "__gptxt_run_program";
+
+/// Environment variables passed on to a sandboxed child, which otherwise starts with an empty
+/// environment so a program can't read API keys or anything else out of it.
+const CHILD_ENV: &[&str] = &["RUSTPYTHONPATH", "PYTHONPATH", "PYTHONHOME"];
+
+/// Runs `program` on RustPython in a child process, which is gptxt itself run with `CHILD_ARG`.
+/// The child can be killed when `options.timeout` passes, and with `options.sandbox` it locks
+/// itself down before the program starts.
+pub fn execute_program(input: &str, program: &str, options: &ExecOptions) -> Result<String, ExecuteError> {
+    let io_err = |e: io::Error| ExecuteError::ExecutionError(e.to_string());
+
+    let exe = env::current_exe().map_err(io_err)?;
+    let request = json!({
+        "input": input,
+        "program": program,
+        "strict": options.strict,
+        "sandbox": options.sandbox,
+        "result_hook": options.result_hook,
+        "result_var": options.result_var,
+        "vars": options.vars,
+        "dump_scope": options.dump_scope,
+    });
+
+    let mut command = Command::new(&exe);
+    command.arg(CHILD_ARG);
+    // Without the sandbox the program sees the same environment it would in-process.
+    if options.sandbox.is_some() {
+        command
+            .env_clear()
+            .envs(CHILD_ENV.iter().filter_map(|var| env::var_os(var).map(|value| (var, value))));
+    }
+
+    let started = Instant::now();
+    let mut child = command
+        // Not even STDERR is shared, so the program has no handle on the terminal.
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ExecuteError::ExecutionError(format!("could not start '{}': {}", exe.display(), e)))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(e) = stdin.write_all(request.to_string().as_bytes()) {
+            if e.kind() != ErrorKind::BrokenPipe {
+                return Err(io_err(e));
+            }
+        }
+    }
+
+    let output = match options.timeout {
+        Some(timeout) => util::wait_with_timeout(child, timeout).map_err(io_err)?,
+        None => Some(child.wait_with_output().map_err(io_err)?),
+    };
+    timing::record("execution", started.elapsed());
+    let Some(output) = output else {
+        return Err(ExecuteError::Timeout(options.timeout.unwrap_or_default()));
+    };
+
+    match serde_json::from_slice::<Value>(&output.stdout).ok().and_then(|reply| outcome_from_json(&reply)) {
+        Some(outcome) => outcome.emit(options.stdout_path),
+        None => {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            Err(ExecuteError::ExecutionError(if stderr.is_empty() { output.status.to_string() } else { stderr }))
+        }
+    }
+}
+
+/// When this process is a child started by `execute_program`, runs the program it was sent,
+/// writes what came of it to STDOUT, and exits; otherwise returns. Called first thing in `main`,
+/// before anything else starts threads the sandbox would get in the way of.
+pub fn serve_if_child() {
+    if env::args_os().nth(1).as_deref() != Some(OsStr::new(CHILD_ARG)) {
+        return;
+    }
+
+    let mut request = String::new();
+    let request = io::stdin()
+        .read_to_string(&mut request)
+        .ok()
+        .and_then(|_| serde_json::from_str::<Value>(&request).ok());
+    let Some(request) = request else {
+        eprintln!("could not read the program to run");
+        std::process::exit(1);
+    };
+
+    let sandbox = request["sandbox"]
+        .as_array()
+        .map(|modules| modules.iter().filter_map(|m| m.as_str().map(str::to_owned)).collect::<Vec<String>>());
+    let vars = request["vars"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|pair| Some((pair[0].as_str()?.to_owned(), pair[1].as_str()?.to_owned())))
+        .collect::<Vec<(String, String)>>();
+    let options = ExecOptions {
+        strict: request["strict"].as_bool().unwrap_or_default(),
+        sandbox: sandbox.as_deref(),
+        result_hook: request["result_hook"].as_str(),
+        result_var: request["result_var"].as_str(),
+        vars: &vars,
+        dump_scope: request["dump_scope"].as_bool().unwrap_or_default(),
+        ..ExecOptions::default()
+    };
+
+    let outcome = execute::run_in_interpreter(
+        request["input"].as_str().unwrap_or_default(),
+        request["program"].as_str().unwrap_or_default(),
+        &options,
+    );
+    let mut stdout = io::stdout();
+    let written = stdout
+        .write_all(outcome_to_json(&outcome).to_string().as_bytes())
+        .and_then(|_| stdout.flush());
+    std::process::exit(if written.is_ok() { 0 } else { 1 });
+}
+
+fn outcome_to_json(outcome: &Outcome) -> Value {
+    let result = match &outcome.result {
+        Ok(result) => json!({ "ok": result }),
+        Err(e) => {
+            let (kind, detail) = match e {
+                ExecuteError::CompileError(detail) => ("compile", detail.clone()),
+                ExecuteError::ExecutionError(detail) => ("execution", detail.clone()),
+                ExecuteError::ResultNotFound(name) => ("result_not_found", name.clone()),
+                ExecuteError::ResultNone(name) => ("result_none", name.clone()),
+                ExecuteError::ResultConversionError(type_name) => ("result_conversion", type_name.clone()),
+                ExecuteError::BlockedImport(module) => ("blocked_import", module.clone()),
+                ExecuteError::Warning(traceback) => ("warning", traceback.clone()),
+                // The interpreter doesn't raise anything else; it would be reported as a failed run.
+                _ => ("execution", e.to_string()),
+            };
+            json!({ "error": kind, "detail": detail })
+        }
+    };
+    json!({ "result": result, "printed": outcome.printed, "scope": outcome.scope })
+}
+
+fn outcome_from_json(reply: &Value) -> Option<Outcome> {
+    let result = &reply["result"];
+    let result = match result["ok"].as_str() {
+        Some(result) => Ok(result.to_owned()),
+        None => {
+            let detail = result["detail"].as_str()?.to_owned();
+            Err(match result["error"].as_str()? {
+                "compile" => ExecuteError::CompileError(detail),
+                "result_not_found" => ExecuteError::ResultNotFound(detail),
+                "result_none" => ExecuteError::ResultNone(detail),
+                "result_conversion" => ExecuteError::ResultConversionError(detail),
+                "blocked_import" => ExecuteError::BlockedImport(detail),
+                "warning" => ExecuteError::Warning(detail),
+                _ => ExecuteError::ExecutionError(detail),
+            })
+        }
+    };
+    let scope = reply["scope"].as_array().map(|variables| {
+        variables
+            .iter()
+            .filter_map(|pair| Some((pair[0].as_str()?.to_owned(), pair[1].as_str()?.to_owned())))
+            .collect()
+    });
+
+    Some(Outcome { result, printed: reply["printed"].as_str().map(str::to_owned), scope })
+}
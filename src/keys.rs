@@ -0,0 +1,74 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+/// How long a rate-limited key is skipped before it's tried again.
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+struct KeyRing {
+    keys: Vec<String>,
+    current: usize,
+    /// When each key may be used again after a rate limit.
+    cooling_until: Vec<Option<Instant>>,
+}
+
+static KEYS: Lazy<Mutex<KeyRing>> = Lazy::new(|| {
+    Mutex::new(KeyRing {
+        keys: Vec::new(),
+        current: 0,
+        cooling_until: Vec::new(),
+    })
+});
+
+/// Starts using the first of `keys`; the rest are rotated to when a key is rate limited.
+pub fn init(keys: Vec<String>) {
+    if let Some(first) = keys.first() {
+        openai::set_key(first.clone());
+    }
+    let mut ring = KEYS.lock().unwrap();
+    ring.cooling_until = vec![None; keys.len()];
+    ring.keys = keys;
+    ring.current = 0;
+}
+
+/// Puts the current key on cooldown and switches to the next key that isn't cooling down.
+/// Returns `false`, keeping the current key, if every other key is still cooling down.
+pub fn rotate_after_rate_limit() -> bool {
+    let mut ring = KEYS.lock().unwrap();
+    let count = ring.keys.len();
+    if count < 2 {
+        return false;
+    }
+
+    let now = Instant::now();
+    let current = ring.current;
+    ring.cooling_until[current] = Some(now + COOLDOWN);
+
+    let next = (1..count)
+        .map(|offset| (current + offset) % count)
+        .find(|&i| ring.cooling_until[i].map_or(true, |until| until <= now));
+    match next {
+        Some(i) => {
+            ring.current = i;
+            openai::set_key(ring.keys[i].clone());
+            true
+        }
+        None => false,
+    }
+}
+
+/// The key in use, masked for messages, when more than one key is configured.
+pub fn current_label() -> Option<String> {
+    let ring = KEYS.lock().unwrap();
+    if ring.keys.len() < 2 {
+        return None;
+    }
+    Some(mask(&ring.keys[ring.current]))
+}
+
+/// Shows only the last four characters of `key`.
+fn mask(key: &str) -> String {
+    let tail: String = key.chars().rev().take(4).collect::<Vec<char>>().into_iter().rev().collect();
+    format!("...{}", tail)
+}
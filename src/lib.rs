@@ -0,0 +1,25 @@
+//! The engine behind the `gptxt` command: generating a Python program for a task with
+//! [`generate::generate_program`] and running it on text with [`execute::execute_program`], along
+//! with reading that text ([`input`]), caching runs ([`exec_cache`]), and checking and splitting
+//! results ([`output`]). The binary adds the command line and the interactive prompts.
+
+#[macro_use]
+pub mod util;
+pub mod bundle;
+pub mod config;
+pub mod exec_cache;
+pub mod execute;
+pub mod export;
+pub mod generate;
+pub mod history;
+pub mod input;
+pub mod isolate;
+pub mod keys;
+pub mod lint;
+pub mod output;
+pub mod sample;
+pub mod sandbox;
+pub mod timing;
+
+pub use execute::{execute_program, ExecOptions, ExecuteError};
+pub use generate::{generate_program, Failure, GenerateOptions, Generation};
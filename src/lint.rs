@@ -0,0 +1,83 @@
+// The parser RustPython itself compiles with, so there's only ever one in the build.
+use rustpython::vm::compiler::parser::ast::{ExcepthandlerKind, Expr, ExprKind, Stmt, StmtKind};
+use rustpython::vm::compiler::parser::parser;
+
+/// Whether a program assigns its result variable, and if so, whether it always does.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResultAssignment {
+    Always,
+    /// Only inside a branch, loop, or exception handler that may not run.
+    Conditional,
+    Never,
+}
+
+/// Checks how `program` assigns the variable `name`, or `None` if it doesn't parse. Only top-level
+/// code is followed; assignments inside functions and classes are treated as conditional.
+pub fn result_assignment(program: &str, name: &str) -> Option<ResultAssignment> {
+    let suite = parser::parse_program(program, "<program>").ok()?;
+
+    Some(if always_assigns(&suite, name) {
+        ResultAssignment::Always
+    } else if ever_assigns(&suite, name) {
+        ResultAssignment::Conditional
+    } else {
+        ResultAssignment::Never
+    })
+}
+
+fn always_assigns(body: &[Stmt], name: &str) -> bool {
+    body.iter().any(|stmt| match &stmt.node {
+        StmtKind::Assign { targets, .. } => targets.iter().any(|target| is_result_target(target, name)),
+        StmtKind::AnnAssign { target, value: Some(_), .. } => is_result_target(target, name),
+        StmtKind::If { body, orelse, .. } => always_assigns(body, name) && always_assigns(orelse, name),
+        StmtKind::With { body, .. } | StmtKind::AsyncWith { body, .. } => always_assigns(body, name),
+        StmtKind::Try { body, handlers, orelse, finalbody } => {
+            always_assigns(finalbody, name)
+                || ((always_assigns(body, name) || always_assigns(orelse, name))
+                    && handlers.iter().all(|handler| match &handler.node {
+                        ExcepthandlerKind::ExceptHandler { body, .. } => always_assigns(body, name),
+                    }))
+        }
+        // Loops may run zero times.
+        _ => false,
+    })
+}
+
+fn ever_assigns(body: &[Stmt], name: &str) -> bool {
+    body.iter().any(|stmt| match &stmt.node {
+        StmtKind::Assign { targets, .. } => targets.iter().any(|target| is_result_target(target, name)),
+        StmtKind::AnnAssign { target, .. } | StmtKind::AugAssign { target, .. } => is_result_target(target, name),
+        StmtKind::For { target, body, orelse, .. } | StmtKind::AsyncFor { target, body, orelse, .. } => {
+            is_result_target(target, name) || ever_assigns(body, name) || ever_assigns(orelse, name)
+        }
+        StmtKind::While { body, orelse, .. } | StmtKind::If { body, orelse, .. } => {
+            ever_assigns(body, name) || ever_assigns(orelse, name)
+        }
+        StmtKind::With { body, .. }
+        | StmtKind::AsyncWith { body, .. }
+        | StmtKind::FunctionDef { body, .. }
+        | StmtKind::AsyncFunctionDef { body, .. }
+        | StmtKind::ClassDef { body, .. } => ever_assigns(body, name),
+        StmtKind::Try { body, handlers, orelse, finalbody } => {
+            ever_assigns(body, name)
+                || ever_assigns(orelse, name)
+                || ever_assigns(finalbody, name)
+                || handlers.iter().any(|handler| match &handler.node {
+                    ExcepthandlerKind::ExceptHandler { body, .. } => ever_assigns(body, name),
+                })
+        }
+        _ => false,
+    })
+}
+
+/// `name`, or a tuple or list that unpacks into it.
+fn is_result_target(target: &Expr, name: &str) -> bool {
+    match &target.node {
+        ExprKind::Name { id, .. } => id == name,
+        ExprKind::Tuple { elts, .. } | ExprKind::List { elts, .. } => {
+            elts.iter().any(|target| is_result_target(target, name))
+        }
+        ExprKind::Starred { value, .. } => is_result_target(value, name),
+        _ => false,
+    }
+}
@@ -1,60 +1,255 @@
 #[macro_use]
-mod util;
+extern crate gptxt;
 
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
+use std::ffi::OsString;
 use std::fs::{self, File};
-use std::io::{stderr, stdout, Read, Seek, Write};
+use std::io::{self, stderr, stdout, BufRead, IsTerminal, Read, Seek, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
-use std::time::Duration;
-use std::{fmt, io};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use clap::{Arg, ArgAction};
 use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
-use crossterm::queue;
-use crossterm::style::Stylize;
-use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
-use crossterm::{execute, terminal};
+use crossterm::terminal::{ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue, terminal};
 use indicatif::ProgressBar;
-use openai::completions::Completion;
+use jsonschema::JSONSchema;
+use openai::chat::ChatCompletionMessage;
 use rustpython::vm;
-use rustpython::vm::PyObjectRef;
 use tempfile::NamedTempFile;
 use tokio::signal::unix::{signal, SignalKind};
-use toml::Value;
 
-/*
-TODO: Export program to a script that also accepts piped input or a file as input.
-*/
+use gptxt::bundle::{Bundle, RunOptions};
+use gptxt::config::{self, Config, ConfigError};
+use gptxt::execute::{execute_program, ExecOptions, ExecuteError, RESULT_VAR};
+use gptxt::generate::{
+    apply_output_wrappers, assemble_messages, complete_text, generate_program, is_fatal_api_error,
+    is_retryable_api_error, prompt_sample, retry_delay, role_name, system_message, Failure, GenerateOptions, Generation,
+    MAX_TEMPERATURE, MODEL, OUTPUT_WRAPPER_IMPORT,
+};
+use gptxt::history::ProgramHistory;
+use gptxt::lint::{self, ResultAssignment};
+use gptxt::util::{self, accent, alert, emphasis, heading, LineEnding};
+use gptxt::{exec_cache, export, input, isolate, keys, output, sample, sandbox, timing};
+
+fn main() {
+    // gptxt runs itself again to execute a program in a child process; see `isolate`.
+    isolate::serve_if_child();
+    std::process::exit(run());
+}
 
+/// Runs gptxt and returns the exit code, once the timing report and warnings have been printed.
 #[tokio::main]
-async fn main() {
-    let args = parse_command_line_arguments();
+async fn run() -> i32 {
+    let mut args = parse_command_line_arguments();
+    if args.profile_timing {
+        timing::enable();
+    }
 
     let mut ctrl_c = signal(SignalKind::interrupt()).expect("Error setting Ctrl+C handler");
 
     let ctrl_c_fut = async {
         ctrl_c.recv().await;
-        print_error!("\nCaught Ctrl+C; exiting.");
-        std::process::exit(0);
+        print_warn!("\ncaught Ctrl+C; exiting.");
     };
 
-    let key = match read_or_create_config() {
-        Ok(k) => k,
+    let started = Instant::now();
+    let config = config::read_or_create_config(args.config.as_deref());
+    timing::record("config read", started.elapsed());
+    let config = match config {
+        Ok(c) => c,
+        // A manually written, piped-in, bundled, or fixture program never calls the API, so the key isn't needed.
+        Err(ConfigError::Created(_) | ConfigError::MissingKey(_) | ConfigError::EmptyKey(_))
+            if args.manual
+                || args.program_stdin
+                || args.from_bundle.is_some()
+                || args.fixture.is_some()
+                || args.preview_tokens =>
+        {
+            Config::default()
+        }
+        Err(ConfigError::Created(path)) => {
+            print_success!("created a new configuration file at: {}", path.display());
+            print_success!("set the 'key' value in the file before using the program.");
+            std::process::exit(1);
+        }
         Err(e) => {
-            print_error!("Error reading config file: {}", e);
+            print_error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    keys::init(config.keys);
+    if let Some(base_url) = config.base_url {
+        openai::set_base_url(base_url);
+    }
+
+    if let Some(theme) = args.theme.as_deref().or(config.theme.as_deref()) {
+        if let Err(e) = util::set_theme(theme) {
+            print_error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if args.temp_dir.is_none() {
+        args.temp_dir = config.temp_dir.map(PathBuf::from);
+    }
+    args.editor = config.editor;
+
+    if config.alt_screen == Some(false) {
+        args.no_alt_screen = true;
+    }
+
+    if args.prompt_tty || config.prompt_tty == Some(true) {
+        if let Err(e) = util::use_prompt_tty() {
+            print_error!("could not open /dev/tty for prompts: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if args.models.is_empty() {
+        args.models = config
+            .models
+            .filter(|m| !m.is_empty())
+            .or_else(|| config.model.map(|m| vec![m]))
+            .unwrap_or_else(|| vec![MODEL.to_owned()]);
+    }
+
+    if config.system_stdin_line == Some(false) {
+        args.no_system_stdin_line = true;
+    }
+
+    // Explicit format flags always win over inference, and inference over `default_format`.
+    if args.infer_format && !args.jsonify && args.split_output.is_none() {
+        if let Some(one_line) = infer_json_format(&args.task) {
+            args.jsonify = true;
+            args.jsonify_one_line = one_line;
+            print_warn!(
+                "inferred {} from the task (--infer-format)",
+                if one_line { "--json --json-one-line" } else { "--json" }
+            );
+        }
+    }
+
+    if !args.jsonify && args.split_output.is_none() {
+        match config.default_format.as_deref() {
+            None | Some("text") => {}
+            Some("json") => args.jsonify = true,
+            Some("json-one-line") => {
+                args.jsonify = true;
+                args.jsonify_one_line = true;
+            }
+            Some(other) => {
+                print_error!("invalid default_format '{}' in config; expected text, json, or json-one-line", other);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if args.output.is_none() {
+        if let Some(template) = &config.default_output {
+            args.output = Some(PathBuf::from(expand_output_template(template, &args.task)));
+        }
+    }
+
+    if let Some(path) = args.system_file.clone().or(config.system_file.map(PathBuf::from)) {
+        match fs::read_to_string(&path) {
+            Ok(message) => args.system_message = Some(message),
+            Err(e) => {
+                print_error!("could not read system message file {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    args.system_append = config.system_append;
+
+    if let Some(path) = &args.from_bundle {
+        let bundle = Bundle::read(path).unwrap_or_else(|e| {
+            print_error!("could not read bundle {}: {}", path.display(), e);
             std::process::exit(1);
+        });
+        print_progress!("replaying bundled program for task: {} (model {})", bundle.task, bundle.model);
+        if let Some(options) = bundle.options {
+            args.apply_run_options(options);
+        }
+        // New input replaces the bundled input when it's given explicitly or piped in.
+        let input = if args.input_file.is_some() || !io::stdin().is_terminal() {
+            read_input_timed(&args)
+        } else {
+            bundle.input
+        };
+        return run_program_once(&args, &input, &bundle.program).await;
+    }
+
+    if args.program_stdin {
+        let program = apply_output_wrappers(&args.generate_options(), read_piped_input(None, false, false).trim());
+        let input = read_input_timed(&args);
+        return run_program_once(&args, &input, &program).await;
+    }
+
+    let input = read_input_timed(&args);
+
+    if args.preview_tokens {
+        preview_tokens(&args.models[0], &assemble_messages(&args.generate_options(), &input, None), args.verbose);
+        timing::print_report();
+        finish_run(args.verbose, args.warnings_as_errors);
+        return 0;
+    }
+
+    if args.describe_input {
+        match describe_input(&args, &args.models[0], &input).await {
+            Ok(description) => println!("{}", description),
+            Err(e) => {
+                print_error!("OpenAI API call failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        timing::print_report();
+        finish_run(args.verbose, args.warnings_as_errors);
+        return 0;
+    }
+
+    let (verbose, warnings_as_errors) = (args.verbose, args.warnings_as_errors);
+    let program_fut = async move {
+        let (mut args, mut input) = (args, input);
+        loop {
+            match execute_program_loop(&input, args).await {
+                ControlFlow::Continue((next_args, result)) => (args, input) = (next_args, result),
+                ControlFlow::Break(code) => return code,
+            }
         }
     };
-    openai::set_key(key);
 
-    let input = read_input(args.input_file.as_deref());
+    let code = tokio::select! {
+        _ = ctrl_c_fut => EXIT_INTERRUPTED,
+        code = program_fut => code,
+    };
+    timing::print_report();
+    finish_run(verbose, warnings_as_errors);
+    code
+}
 
-    let program_fut = execute_program_loop(&input, args);
+/// Lists the run's warnings with `--verbose` and fails the run over them with `--warnings-as-errors`.
+fn finish_run(verbose: bool, warnings_as_errors: bool) {
+    let warnings = util::warnings();
+    if warnings.is_empty() {
+        return;
+    }
 
-    tokio::select! {
-        _ = ctrl_c_fut => {}
-        _ = program_fut => {}
+    if verbose {
+        print_progress!("\nwarnings ({}):", warnings.len());
+        for warning in &warnings {
+            ui_println!("  - {}", warning);
+        }
+    }
+    if warnings_as_errors {
+        print_error!("{} warning(s) treated as errors (--warnings-as-errors)", warnings.len());
+        std::process::exit(EXIT_WARNINGS);
     }
 }
 
@@ -62,30 +257,427 @@ struct Arguments {
     task: String,
     temperature: f32,
     max_tokens: u16,
+    /// Extra attempts at an API request that failed with a transient error.
+    retries: u32,
+    /// How long a program may run; `None` without `--timeout` or with `--timeout 0`.
+    timeout: Option<Duration>,
     input_file: Option<String>,
     show_lines: Option<u16>,
     jsonify: bool,
     jsonify_one_line: bool,
     show_prompt: bool,
+    dedupe_history: bool,
+    exec_cache: bool,
+    lint_program: bool,
+    strict_result: bool,
+    quiet_on_success: bool,
+    quiet_api: bool,
+    json_schema: Option<JSONSchema>,
+    compare: Option<String>,
+    show_diff_on_edit: bool,
+    split_output: Option<PathBuf>,
+    no_system_stdin_line: bool,
+    no_progress: bool,
+    trace: Option<PathBuf>,
+    manual: bool,
+    line_endings: LineEnding,
+    system_file: Option<PathBuf>,
+    system_message: Option<String>,
+    /// The config's `system_append`, added to the system message before `instructions`.
+    system_append: Option<String>,
+    /// Each `--instruct`, added to the system message.
+    instructions: Vec<String>,
+    program_stdin: bool,
+    verbose: bool,
+    max_input_bytes: Option<u64>,
+    truncate_input: bool,
+    gzip: bool,
+    output: Option<PathBuf>,
+    result_hook: Option<String>,
+    /// The variable the result is read from; `result` unless `--result-var` says otherwise.
+    result_var: String,
+    /// `--var` names and values, set as string variables next to `data`.
+    vars: Vec<(String, String)>,
+    bundle: Option<PathBuf>,
+    export: Option<PathBuf>,
+    export_sh: Option<PathBuf>,
+    from_bundle: Option<PathBuf>,
+    explain_error: bool,
+    config: Option<PathBuf>,
+    profile_timing: bool,
+    /// Models to try in order; filled from `--model`, the `models` or `model` config key, or `MODEL`.
+    models: Vec<String>,
+    squash_blank_lines: bool,
+    remember_choice: bool,
+    yes: bool,
+    dry_run: bool,
+    dump_scope: bool,
+    prompt_tty: bool,
+    max_lines_output: Option<usize>,
+    fixture: Option<PathBuf>,
+    theme: Option<String>,
+    no_post_menu: bool,
+    diff_against: Option<PathBuf>,
+    temp_dir: Option<PathBuf>,
+    /// From the `editor` config key; `None` falls back to `$VISUAL`, `$EDITOR`, then `vi`.
+    editor: Option<String>,
+    safe: bool,
+    allow_modules: Vec<String>,
+    describe_input: bool,
+    preview_tokens: bool,
+    validate_against: Vec<PathBuf>,
+    separate_stdout: Option<PathBuf>,
+    show_head_tail: Option<(u16, u16)>,
+    tail_lines: Option<u16>,
+    sample_lines: Option<u16>,
+    /// Picks the `--sample-lines` lines; from `--seed`, or the clock when it isn't given.
+    seed: u64,
+    infer_format: bool,
+    no_alt_screen: bool,
+    warnings_as_errors: bool,
+    format_program: Option<String>,
 }
 
+impl Arguments {
+    /// The options a bundle records so `--from-bundle` runs its program the same way.
+    fn run_options(&self) -> RunOptions {
+        RunOptions {
+            vars: self.vars.clone(),
+            result_var: self.result_var.clone(),
+            jsonify: self.jsonify,
+            jsonify_one_line: self.jsonify_one_line,
+            split_output: self.split_output.clone(),
+            line_endings: self.line_endings,
+            strict_result: self.strict_result,
+            result_hook: self.result_hook.clone(),
+            safe: self.safe,
+            allow_modules: self.allow_modules.clone(),
+            timeout: self.timeout,
+        }
+    }
+
+    /// Takes a bundle's options in place of those on the command line.
+    fn apply_run_options(&mut self, options: RunOptions) {
+        self.vars = options.vars;
+        self.result_var = options.result_var;
+        self.jsonify = options.jsonify;
+        self.jsonify_one_line = options.jsonify_one_line;
+        self.split_output = options.split_output;
+        self.line_endings = options.line_endings;
+        self.strict_result = options.strict_result;
+        self.result_hook = options.result_hook;
+        self.safe = options.safe;
+        self.allow_modules = options.allow_modules;
+        self.timeout = options.timeout;
+    }
+
+    /// The flags that shape generation, taken fresh each time since the regen prompt changes some.
+    fn generate_options(&self) -> GenerateOptions {
+        GenerateOptions {
+            task: self.task.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            system_message: self.system_message.clone(),
+            instructions: self.system_append.iter().chain(&self.instructions).cloned().collect(),
+            no_system_stdin_line: self.no_system_stdin_line,
+            show_lines: self.show_lines,
+            squash_blank_lines: self.squash_blank_lines,
+            show_head_tail: self.show_head_tail,
+            tail_lines: self.tail_lines,
+            sample_lines: self.sample_lines,
+            seed: self.seed,
+            jsonify: self.jsonify,
+            jsonify_one_line: self.jsonify_one_line,
+            split_output: self.split_output.is_some(),
+            result_var: self.result_var.clone(),
+            vars: self.vars.iter().map(|(name, _)| name.clone()).collect(),
+            fixture: self.fixture.clone(),
+            trace: self.trace.clone(),
+            quiet: self.quiet_api,
+        }
+    }
+}
+
+/// Parses the command line as if the config's `default_flags` came right after the program name,
+/// so they're checked together with the real flags. A default the command line repeats or
+/// conflicts with is left out, so flags given on the command line override it.
 fn parse_command_line_arguments() -> Arguments {
-    let matches = clap::Command::new("GPT text processing assistant")
-        .version("1.0")
+    let mut argv = env::args_os();
+    let program = argv.next();
+    let given = argv.collect::<Vec<OsString>>();
+
+    // Only `--config` is needed to find the defaults; a config that can't be read is reported
+    // once the command line is parsed.
+    let command = command_line();
+    let default_flags = config::read_default_flags(config_flag(&given).as_deref()).unwrap_or_default();
+    let default_flags = drop_overridden_defaults(&command, default_flags, &given);
+    let default_flags = default_flags.as_slice();
+    let argv = program
+        .into_iter()
+        .chain(default_flags.iter().map(Into::into))
+        .chain(given)
+        .collect::<Vec<OsString>>();
+
+    let matches = command
+        .try_get_matches_from(argv)
+        .unwrap_or_else(|e| {
+            let _ = e.print();
+            if e.use_stderr() {
+                note_default_flags(default_flags);
+            }
+            std::process::exit(e.exit_code());
+        });
+
+    if matches.get_flag("version") {
+        print_version(matches.get_flag("verbose"));
+        std::process::exit(0);
+    }
+
+    let task = matches.get_one::<String>("task").cloned().unwrap_or_default();
+    let temperature = matches.get_one::<f32>("temp").unwrap();
+    let timeout = match matches.get_one::<u64>("timeout") {
+        None | Some(0) => None,
+        Some(&secs) => Some(Duration::from_secs(secs)),
+    };
+    let max_tokens = matches.get_one::<u16>("max-tokens").unwrap();
+    let retries = *matches.get_one::<u32>("retries").unwrap();
+    let jsonify = matches.get_flag("json");
+    let jsonify_one_line = matches.get_flag("json-one-line");
+    let input_file = matches.get_one::<String>("input");
+    let show_lines = matches.get_one::<u16>("show-lines");
+    let show_prompt = matches.get_flag("show-prompt");
+    let squash_blank_lines = matches.get_flag("squash-blank-lines");
+    let remember_choice = matches.get_flag("remember-choice");
+    let yes = matches.get_flag("yes");
+    let dry_run = matches.get_flag("dry-run");
+    let dump_scope = matches.get_flag("dump-scope");
+    let prompt_tty = matches.get_flag("prompt-tty");
+    let max_lines_output = matches.get_one::<usize>("max-lines-output");
+    let theme = matches.get_one::<String>("theme");
+    let no_post_menu = matches.get_flag("no-post-menu");
+    let diff_against = matches.get_one::<PathBuf>("diff-against");
+    let temp_dir = matches.get_one::<PathBuf>("temp-dir");
+    let safe = matches.get_flag("safe");
+    let describe_input = matches.get_flag("describe-input");
+    let preview_tokens = matches.get_flag("preview-tokens");
+    let separate_stdout = matches.get_one::<PathBuf>("separate-stdout");
+    let show_head_tail = matches.get_one::<(u16, u16)>("show-head-tail");
+    let tail_lines = matches.get_one::<u16>("tail-lines");
+    let sample_lines = matches.get_one::<u16>("sample-lines");
+    let seed = matches.get_one::<u64>("seed").copied().unwrap_or_else(sample::fresh_seed);
+    let infer_format = matches.get_flag("infer-format");
+    let no_alt_screen = matches.get_flag("no-alt-screen");
+    let warnings_as_errors = matches.get_flag("warnings-as-errors");
+    let format_program = matches.get_one::<String>("format-program");
+    let validate_against = matches
+        .get_many::<PathBuf>("validate-against")
+        .map(|paths| paths.cloned().collect())
+        .unwrap_or_default();
+    let instructions = matches
+        .get_many::<String>("instruct")
+        .map(|instructions| instructions.cloned().collect())
+        .unwrap_or_default();
+    let allow_modules = matches
+        .get_many::<String>("allow-module")
+        .map(|modules| modules.cloned().collect())
+        .unwrap_or_default();
+    // Only the flag, not the environment, so a stray variable can't replace real generation.
+    let fixture = matches.get_one::<PathBuf>("fixture").cloned();
+    let dedupe_history = matches.get_flag("dedupe-history");
+    let exec_cache = matches.get_flag("exec-cache");
+    let lint_program = matches.get_flag("lint-program");
+    let strict_result = matches.get_flag("strict-result");
+    let quiet_on_success = matches.get_flag("quiet-on-success");
+    let quiet_api = matches.get_flag("quiet-api");
+    let compare = matches.get_one::<String>("compare");
+    let show_diff_on_edit = matches.get_flag("show-diff-on-edit");
+    let split_output = matches.get_one::<PathBuf>("split-output");
+    let no_system_stdin_line = matches.get_flag("no-system-stdin-line");
+    let no_progress = matches.get_flag("no-progress");
+    let trace = matches.get_one::<PathBuf>("trace");
+    let manual = matches.get_flag("manual");
+    let program_stdin = matches.get_flag("program-stdin");
+    let verbose = matches.get_flag("verbose");
+    let max_input_bytes = matches.get_one::<u64>("max-input-bytes");
+    let truncate_input = matches.get_flag("truncate-input");
+    let gzip = matches.get_flag("gzip");
+    let result_hook = matches.get_one::<String>("result-hook");
+    let vars: Vec<(String, String)> = matches
+        .get_many::<(String, String)>("var")
+        .map(|vars| vars.cloned().collect())
+        .unwrap_or_default();
+    let result_var = matches.get_one::<String>("result-var").unwrap().clone();
+    for (i, (name, _)) in vars.iter().enumerate() {
+        if vars[..i].iter().any(|(earlier, _)| earlier == name) {
+            print_error!("--var {} is given more than once", name);
+            note_default_flags(default_flags);
+            std::process::exit(1);
+        }
+        if *name == result_var {
+            print_error!("--var {} would be overwritten by the result; choose another name", name);
+            note_default_flags(default_flags);
+            std::process::exit(1);
+        }
+    }
+    let output = matches.get_one::<PathBuf>("output");
+    let bundle = matches.get_one::<PathBuf>("bundle");
+    let export = matches.get_one::<PathBuf>("export");
+    let export_sh = matches.get_one::<PathBuf>("export-sh");
+    let from_bundle = matches.get_one::<PathBuf>("from-bundle");
+    let explain_error = matches.get_flag("explain-error");
+    let config = matches.get_one::<PathBuf>("config");
+    let profile_timing = matches.get_flag("profile-timing");
+    let models: Vec<String> = matches
+        .get_one::<String>("model")
+        .map(|list| {
+            list.split(',')
+                .map(str::trim)
+                .filter(|m| !m.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    if matches.contains_id("model") && models.is_empty() {
+        print_error!("--model is empty; give a model name such as {}", MODEL);
+        std::process::exit(1);
+    }
+    let system_file = matches.get_one::<PathBuf>("system-file");
+    let line_endings = matches
+        .get_one::<String>("line-endings")
+        .map_or(LineEnding::Keep, |s| s.parse().expect("clap only accepts valid line endings"));
+    let json_schema = matches.get_one::<String>("json-schema").map(|path| {
+        load_json_schema(path).unwrap_or_else(|e| {
+            print_error!("could not load JSON schema '{}': {}", path, e);
+            std::process::exit(1);
+        })
+    });
+
+    if matches.get_flag("no-color") {
+        util::disable_color();
+    }
+
+    validate_json_flags(jsonify, jsonify_one_line, default_flags);
+    validate_generation_flags(*temperature, *max_tokens, default_flags);
+
+    let task = if matches.get_flag("expand-env") {
+        util::expand_env_vars(&task).unwrap_or_else(|e| {
+            print_error!("could not expand task: {}", e);
+            std::process::exit(1);
+        })
+    } else {
+        task
+    };
+
+    Arguments {
+        task,
+        temperature: *temperature,
+        max_tokens: *max_tokens,
+        retries,
+        timeout,
+        input_file: input_file.cloned(),
+        show_lines: show_lines.cloned(),
+        jsonify,
+        jsonify_one_line,
+        show_prompt,
+        dedupe_history,
+        exec_cache,
+        lint_program,
+        strict_result,
+        quiet_on_success,
+        quiet_api,
+        json_schema,
+        compare: compare.cloned(),
+        show_diff_on_edit,
+        split_output: split_output.cloned(),
+        no_system_stdin_line,
+        no_progress,
+        trace: trace.cloned(),
+        manual,
+        line_endings,
+        system_file: system_file.cloned(),
+        system_message: None,
+        system_append: None,
+        instructions,
+        program_stdin,
+        verbose,
+        max_input_bytes: max_input_bytes.cloned(),
+        truncate_input,
+        gzip,
+        output: output.cloned(),
+        result_hook: result_hook.cloned(),
+        result_var,
+        vars,
+        bundle: bundle.cloned(),
+        export: export.cloned(),
+        export_sh: export_sh.cloned(),
+        from_bundle: from_bundle.cloned(),
+        explain_error,
+        config: config.cloned(),
+        profile_timing,
+        models,
+        squash_blank_lines,
+        remember_choice,
+        yes,
+        dry_run,
+        dump_scope,
+        prompt_tty,
+        max_lines_output: max_lines_output.cloned(),
+        fixture,
+        theme: theme.cloned(),
+        no_post_menu,
+        diff_against: diff_against.cloned(),
+        temp_dir: temp_dir.cloned(),
+        editor: None,
+        safe,
+        allow_modules,
+        describe_input,
+        preview_tokens,
+        validate_against,
+        separate_stdout: separate_stdout.cloned(),
+        show_head_tail: show_head_tail.cloned(),
+        tail_lines: tail_lines.cloned(),
+        sample_lines: sample_lines.cloned(),
+        seed,
+        infer_format,
+        no_alt_screen,
+        warnings_as_errors,
+        format_program: format_program.cloned(),
+    }
+}
+
+/// gptxt's flags, before anything is parsed.
+fn command_line() -> clap::Command {
+    clap::Command::new("GPT text processing assistant")
+        .version(env!("CARGO_PKG_VERSION"))
+        .disable_version_flag(true)
         .arg_required_else_help(true)
+        .args_override_self(true)
         .arg(
             Arg::new("task")
                 .index(1)
-                .required(true)
+                .required_unless_present_any(["version", "program-stdin", "from-bundle", "describe-input"])
                 .help("Description of a text processing task"),
         )
+        .arg(
+            Arg::new("expand-env")
+                .long("expand-env")
+                .action(ArgAction::SetTrue)
+                .help("Expand ${VAR} references in the task from the environment"),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .short('T')
+                .value_parser(u64::from_str)
+                .help("Stop a program that runs longer than this many seconds (0 for no limit, the default)"),
+        )
         .arg(
             Arg::new("temp")
                 .long("temp")
                 .short('t')
                 .default_value("0.25")
                 .value_parser(f32::from_str)
-                .help("Set GPT randomness/temperature (0.05-1.0; lower = more deterministic)"),
+                .help("Set GPT randomness/temperature (0-1.0; lower = more deterministic)"),
         )
         .arg(
             Arg::new("max-tokens")
@@ -95,6 +687,24 @@ fn parse_command_line_arguments() -> Arguments {
                 .value_parser(u16::from_str)
                 .help("Set GPT response token limit"),
         )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .default_value("3")
+                .value_parser(u32::from_str)
+                .help("Retry API requests that hit a rate limit, server error, or timeout this many times"),
+        )
+        .arg(
+            Arg::new("model")
+                .long("model")
+                .short('M')
+                .help("Model to generate with; a comma-separated list falls back to each in order on failure [default: gpt-3.5-turbo]"),
+        )
+        .arg(
+            Arg::new("compare")
+                .long("compare")
+                .help("Also generate a program with this model and choose which one to use"),
+        )
         .arg(
             Arg::new("json")
                 .long("json")
@@ -108,12 +718,114 @@ fn parse_command_line_arguments() -> Arguments {
                 .action(ArgAction::SetTrue)
                 .help("Serialize JSON output to one line (requires --json)"),
         )
+        .arg(
+            Arg::new("infer-format")
+                .long("infer-format")
+                .action(ArgAction::SetTrue)
+                .help("Apply --json (or --json-one-line) when the task asks for JSON output and no format flag is given"),
+        )
+        .arg(
+            Arg::new("json-schema")
+                .long("json-schema")
+                .help("Validate the program's output against a JSON Schema file"),
+        )
+        .arg(
+            Arg::new("split-output")
+                .long("split-output")
+                .conflicts_with("json")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Treat 'result' as a dict of file names to contents and write each file into this directory"),
+        )
+        .arg(
+            Arg::new("result-hook")
+                .long("result-hook")
+                .help("Python expression evaluated against `result` to produce the final output, e.g. '\\n'.join(result)"),
+        )
+        .arg(
+            Arg::new("result-var")
+                .long("result-var")
+                .default_value(RESULT_VAR)
+                .value_parser(parse_result_var)
+                .help("Variable the program stores its result in; the model is told to use it"),
+        )
+        .arg(
+            Arg::new("var")
+                .long("var")
+                .action(ArgAction::Append)
+                .value_parser(parse_var)
+                .help("Set a string variable for the program next to `data`, given as NAME=VALUE (repeatable)"),
+        )
+        .arg(
+            Arg::new("separate-stdout")
+                .long("separate-stdout")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write anything the program prints to this file instead of STDERR"),
+        )
+        .arg(
+            Arg::new("dump-scope")
+                .long("dump-scope")
+                .action(ArgAction::SetTrue)
+                .help("Print the program's variables and their values after it runs"),
+        )
+        .arg(
+            Arg::new("max-lines-output")
+                .long("max-lines-output")
+                .value_parser(usize::from_str)
+                .help("Show at most N lines of the result on a terminal, with a count of the rest (display only)"),
+        )
+        .arg(
+            Arg::new("diff-against")
+                .long("diff-against")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Compare the result to this file, printing a diff and exiting non-zero if they differ"),
+        )
+        .arg(
+            Arg::new("line-endings")
+                .long("line-endings")
+                .value_parser(["keep", "lf", "crlf"])
+                .default_value("keep")
+                .help("Normalize line endings in the result"),
+        )
         .arg(
             Arg::new("input")
                 .long("input")
                 .short('i')
                 .help("Read data from a file instead of STDIN"),
         )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .conflicts_with("split-output")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write the result to a file instead of STDOUT"),
+        )
+        .arg(
+            Arg::new("validate-against")
+                .long("validate-against")
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Comma-separated files to also run each program against, reporting results before the run prompt"),
+        )
+        .arg(
+            Arg::new("max-input-bytes")
+                .long("max-input-bytes")
+                .value_parser(u64::from_str)
+                .help("Fail if the input is larger than this many bytes"),
+        )
+        .arg(
+            Arg::new("truncate-input")
+                .long("truncate-input")
+                .requires("max-input-bytes")
+                .action(ArgAction::SetTrue)
+                .help("Truncate input beyond --max-input-bytes with a warning instead of failing"),
+        )
+        .arg(
+            Arg::new("gzip")
+                .long("gzip")
+                .action(ArgAction::SetTrue)
+                .help("Decompress gzip input (detected automatically from its header or a .gz file name)"),
+        )
         .arg(
             Arg::new("show-lines")
                 .long("show-lines")
@@ -121,6 +833,55 @@ fn parse_command_line_arguments() -> Arguments {
                 .value_parser(u16::from_str)
                 .help("Show GPT the first N lines of the input to help it generate the program"),
         )
+        .arg(
+            Arg::new("show-head-tail")
+                .long("show-head-tail")
+                .conflicts_with("show-lines")
+                .value_parser(parse_head_tail)
+                .help("Show GPT the first H and last T lines of the input, given as H:T"),
+        )
+        .arg(
+            Arg::new("tail-lines")
+                .long("tail-lines")
+                .conflicts_with_all(["show-lines", "show-head-tail"])
+                .value_parser(u16::from_str)
+                .help("Show GPT the last N lines of the input instead of the first"),
+        )
+        .arg(
+            Arg::new("sample-lines")
+                .long("sample-lines")
+                .conflicts_with_all(["show-lines", "show-head-tail", "tail-lines"])
+                .value_parser(u16::from_str)
+                .help("Show GPT N lines picked at random from the input, in their original order"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .requires("sample-lines")
+                .value_parser(u64::from_str)
+                .help("Seed for --sample-lines; the same seed picks the same lines [default: random]"),
+        )
+        .arg(
+            Arg::new("squash-blank-lines")
+                .long("squash-blank-lines")
+                .requires("show-lines")
+                .action(ArgAction::SetTrue)
+                .help("Collapse runs of blank lines in the --show-lines sample (the program still gets the input unchanged)"),
+        )
+        .arg(
+            Arg::new("describe-input")
+                .long("describe-input")
+                .conflicts_with_all(["manual", "program-stdin", "from-bundle"])
+                .action(ArgAction::SetTrue)
+                .help("Have the model describe a sample of the input (--show-lines, or 20 lines) instead of running a task"),
+        )
+        .arg(
+            Arg::new("preview-tokens")
+                .long("preview-tokens")
+                .conflicts_with_all(["manual", "program-stdin", "from-bundle", "describe-input"])
+                .action(ArgAction::SetTrue)
+                .help("Print how many tokens the prompt is and exit; --verbose also lists the tokens"),
+        )
         .arg(
             Arg::new("show-prompt")
                 .long("show-prompt")
@@ -128,226 +889,981 @@ fn parse_command_line_arguments() -> Arguments {
                 .action(ArgAction::SetTrue)
                 .help("Print the prompt, including the system message and any included lines"),
         )
-        .get_matches();
+        .arg(
+            Arg::new("dedupe-history")
+                .long("dedupe-history")
+                .action(ArgAction::SetTrue)
+                .help("Remember generated programs across runs and treat previously seen programs as duplicates on regen"),
+        )
+        .arg(
+            Arg::new("lint-program")
+                .long("lint-program")
+                .action(ArgAction::SetTrue)
+                .help("Warn before running a program that may not assign `result`"),
+        )
+        .arg(
+            Arg::new("exec-cache")
+                .long("exec-cache")
+                .action(ArgAction::SetTrue)
+                .help("Reuse the result and output of an identical earlier run instead of running the program again"),
+        )
+        .arg(
+            Arg::new("warnings-as-errors")
+                .long("warnings-as-errors")
+                .action(ArgAction::SetTrue)
+                .help("Exit with a distinct code if any warning was printed, even if the run succeeded"),
+        )
+        .arg(
+            Arg::new("strict-result")
+                .long("strict-result")
+                .action(ArgAction::SetTrue)
+                .help("Exit with a distinct code if the program doesn't set 'result', sets it to None, or raises a warning"),
+        )
+        .arg(
+            Arg::new("no-color")
+                .long("no-color")
+                .action(ArgAction::SetTrue)
+                .help("Disable colored output (also respects NO_COLOR)"),
+        )
+        .arg(
+            Arg::new("theme")
+                .long("theme")
+                .value_parser(["default", "mono", "high-contrast"])
+                .help("Color theme for the interactive UI [default: default]"),
+        )
+        .arg(
+            Arg::new("safe")
+                .long("safe")
+                .action(ArgAction::SetTrue)
+                .help("Run programs in the sandbox: no file, network, or process access, and only allowlisted imports"),
+        )
+        .arg(
+            Arg::new("allow-module")
+                .long("allow-module")
+                .action(ArgAction::Append)
+                .help("Also allow importing this module in --safe mode and previews (repeatable)"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .action(ArgAction::SetTrue)
+                .help("Print additional detail (build information with --version, a reproduction command after generation, warnings at the end of a run)"),
+        )
+        .arg(
+            Arg::new("version")
+                .long("version")
+                .short('V')
+                .action(ArgAction::SetTrue)
+                .help("Print version"),
+        )
+        .arg(
+            Arg::new("explain-error")
+                .long("explain-error")
+                .action(ArgAction::SetTrue)
+                .help("Ask the model to explain why a program failed before offering to regenerate it"),
+        )
+        .arg(
+            Arg::new("yes")
+                .long("yes")
+                .short('y')
+                .conflicts_with_all(["manual", "compare"])
+                .action(ArgAction::SetTrue)
+                .help("Run the generated program without asking and exit non-zero if it fails"),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .conflicts_with_all(["yes", "program-stdin", "describe-input", "preview-tokens"])
+                .action(ArgAction::SetTrue)
+                .help("Print the generated program and exit without running it"),
+        )
+        .arg(
+            Arg::new("remember-choice")
+                .long("remember-choice")
+                .action(ArgAction::SetTrue)
+                .help("After you run a program once, run later programs in the session without asking"),
+        )
+        .arg(
+            Arg::new("no-post-menu")
+                .long("no-post-menu")
+                .action(ArgAction::SetTrue)
+                .help("Don't offer to copy, save, or pipe the result after a successful run"),
+        )
+        .arg(
+            Arg::new("format-program")
+                .long("format-program")
+                .help("Pipe each generated program through this shell command (e.g. 'black -q -') and use its output"),
+        )
+        .arg(
+            Arg::new("quiet-on-success")
+                .long("quiet-on-success")
+                .action(ArgAction::SetTrue)
+                .help("Only show the generated program if running it fails"),
+        )
+        .arg(
+            Arg::new("quiet-api")
+                .long("quiet-api")
+                .action(ArgAction::SetTrue)
+                .help("Hide the generation spinner, retry and fallback notices, the prompt, and the generated program; still prompt to run it"),
+        )
+        .arg(
+            Arg::new("system-file")
+                .long("system-file")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Load the system message from a file instead of using the built-in one"),
+        )
+        .arg(
+            Arg::new("instruct")
+                .long("instruct")
+                .action(ArgAction::Append)
+                .help("Add an instruction to the system message, e.g. \"prefer the csv module\" (repeatable)"),
+        )
+        .arg(
+            Arg::new("no-system-stdin-line")
+                .long("no-system-stdin-line")
+                .action(ArgAction::SetTrue)
+                .help("Omit the `data = sys.stdin.read()` preamble from the system message"),
+        )
+        .arg(
+            Arg::new("prompt-tty")
+                .long("prompt-tty")
+                .action(ArgAction::SetTrue)
+                .help("Show prompts, programs, and progress on /dev/tty instead of STDERR"),
+        )
+        .arg(
+            Arg::new("no-progress")
+                .long("no-progress")
+                .action(ArgAction::SetTrue)
+                .help("Don't show progress spinners"),
+        )
+        .arg(
+            Arg::new("program-stdin")
+                .long("program-stdin")
+                .requires("input")
+                .conflicts_with("manual")
+                .action(ArgAction::SetTrue)
+                .help("Read the program from STDIN and run it without calling the API (requires --input for data)"),
+        )
+        .arg(
+            Arg::new("manual")
+                .long("manual")
+                .action(ArgAction::SetTrue)
+                .help("Write the program yourself in the editor instead of generating it"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Read configuration from this TOML, JSON, or YAML file instead of the default location"),
+        )
+        .arg(
+            Arg::new("profile-timing")
+                .long("profile-timing")
+                .action(ArgAction::SetTrue)
+                .help("Print how long each phase (config, input, API, interpreter, execution) took"),
+        )
+        .arg(
+            Arg::new("fixture")
+                .long("fixture")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("For testing: use the program in this file instead of calling the API"),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Append every API request and response to this file as JSON lines"),
+        )
+        .arg(
+            Arg::new("bundle")
+                .long("bundle")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write the task, model, parameters, prompt, input, and program to this file for each generated program"),
+        )
+        .arg(
+            Arg::new("export")
+                .long("export")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write the program as a standalone Python script that reads a file argument or STDIN"),
+        )
+        .arg(
+            Arg::new("export-sh")
+                .long("export-sh")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write a /bin/sh script that runs the program on the same input with the same output options"),
+        )
+        .arg(
+            Arg::new("from-bundle")
+                .long("from-bundle")
+                .conflicts_with_all(["manual", "program-stdin", "compare"])
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Run the program from a --bundle file against new input, or the bundled input if none is given"),
+        )
+        .arg(
+            Arg::new("no-alt-screen")
+                .long("no-alt-screen")
+                .action(ArgAction::SetTrue)
+                .help("Run the editor inline instead of on the terminal's alternate screen"),
+        )
+        .arg(
+            Arg::new("temp-dir")
+                .long("temp-dir")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Directory for the temporary file used when editing programs [default: $TMPDIR]"),
+        )
+        .arg(
+            Arg::new("show-diff-on-edit")
+                .long("show-diff-on-edit")
+                .action(ArgAction::SetTrue)
+                .help("Show a diff of your changes after editing a program"),
+        )
+}
 
-    let task = matches.get_one::<String>("task").unwrap();
-    let temperature = matches.get_one::<f32>("temp").unwrap();
-    let max_tokens = matches.get_one::<u16>("max-tokens").unwrap();
-    let jsonify = matches.get_flag("json");
-    let jsonify_one_line = matches.get_flag("json-one-line");
-    let input_file = matches.get_one::<String>("input");
-    let show_lines = matches.get_one::<u16>("show-lines");
-    let show_prompt = matches.get_flag("show-prompt");
+/// The value of `--config` on the command line, if given.
+fn config_flag(given: &[OsString]) -> Option<PathBuf> {
+    let mut args = given.iter().take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
 
-    validate_json_flags(jsonify, jsonify_one_line);
+/// Splits `args` into flags, each with the values that belong to it, and the other arguments,
+/// which come with `None`.
+fn group_flags<'a>(command: &'a clap::Command, args: &[String]) -> Vec<(Option<&'a Arg>, Vec<String>)> {
+    let mut groups = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            groups.extend(args.by_ref().map(|arg| (None, vec![arg.clone()])));
+            break;
+        }
+        let (found, inline_value) = if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = long.split_once('=').map_or((long, None), |(name, value)| (name, Some(value)));
+            (command.get_arguments().find(|a| a.get_long() == Some(name)), value.is_some())
+        } else if let Some(short) = arg.strip_prefix('-').and_then(|short| short.chars().next()) {
+            (command.get_arguments().find(|a| a.get_short() == Some(short)), arg.len() > 2)
+        } else {
+            (None, false)
+        };
+        let mut group = vec![arg.clone()];
+        if let Some(found) = found {
+            if found.get_action().takes_values() && !inline_value {
+                group.extend(args.next().cloned());
+            }
+        }
+        groups.push((found, group));
+    }
+    groups
+}
 
-    Arguments {
-        task: task.clone(),
-        temperature: *temperature,
-        max_tokens: *max_tokens,
-        input_file: input_file.cloned(),
-        show_lines: show_lines.cloned(),
-        jsonify,
-        jsonify_one_line,
-        show_prompt,
+/// Leaves out each default flag, with its values, that a flag in `given` replaces: the same flag
+/// again, unless it's one that can be given several times, or a flag it conflicts with.
+fn drop_overridden_defaults(command: &clap::Command, default_flags: Vec<String>, given: &[OsString]) -> Vec<String> {
+    let given = given.iter().map(|arg| arg.to_string_lossy().into_owned()).collect::<Vec<String>>();
+    let given = group_flags(command, &given).into_iter().filter_map(|(arg, _)| arg).collect::<Vec<&Arg>>();
+    let conflicts = |a: &Arg, b: &Arg| {
+        command.get_arg_conflicts_with(a).iter().any(|c| c.get_id() == b.get_id())
+            || command.get_arg_conflicts_with(b).iter().any(|c| c.get_id() == a.get_id())
+    };
+    let replaced = |default: &Arg| {
+        given.iter().any(|flag| {
+            (flag.get_id() == default.get_id() && !matches!(default.get_action(), ArgAction::Append))
+                || conflicts(default, flag)
+        })
+    };
+
+    group_flags(command, &default_flags)
+        .into_iter()
+        .filter(|(arg, _)| !arg.is_some_and(replaced))
+        .flat_map(|(_, group)| group)
+        .collect()
+}
+
+/// Whether the task asks for JSON output, and if so whether on one line.
+fn infer_json_format(task: &str) -> Option<bool> {
+    let task = task.to_lowercase();
+    let words = task
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>();
+
+    if !words.contains(&"json") {
+        return None;
     }
+    let one_line = ["one line", "single line", "one-line", "single-line", "compact", "minified"]
+        .iter()
+        .any(|phrase| task.contains(phrase));
+    Some(one_line)
 }
 
-fn validate_json_flags(jsonify: bool, jsonify_one_line: bool) {
-    if jsonify_one_line && !jsonify {
-        print_error!("Error: --json-one-line requires --json to be set.");
-        std::process::exit(1);
+fn parse_head_tail(s: &str) -> Result<(u16, u16), String> {
+    let (head, tail) = s.split_once(':').ok_or("expected H:T, e.g. 5:3")?;
+    let head = head.parse::<u16>().map_err(|e| format!("invalid head line count '{}': {}", head, e))?;
+    let tail = tail.parse::<u16>().map_err(|e| format!("invalid tail line count '{}': {}", tail, e))?;
+    Ok((head, tail))
+}
+
+/// Parses `--var NAME=VALUE`. The value is everything after the first `=`, so it may contain more.
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s.split_once('=').ok_or("expected NAME=VALUE, e.g. delimiter=,")?;
+    check_variable_name(name)?;
+    if name == RESULT_VAR {
+        return Err(format!("'{}' is reserved; choose another name", name));
+    }
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+fn parse_result_var(s: &str) -> Result<String, String> {
+    check_variable_name(s)?;
+    Ok(s.to_owned())
+}
+
+/// Accepts names a program can assign and that don't clash with gptxt's own: `data` and dunders.
+fn check_variable_name(name: &str) -> Result<(), String> {
+    let is_identifier = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier || PYTHON_KEYWORDS.contains(&name) {
+        return Err(format!("'{}' is not a valid Python variable name", name));
+    }
+    if name == "data" || name.starts_with("__") {
+        return Err(format!("'{}' is reserved; choose another name", name));
     }
+    Ok(())
 }
 
-fn read_or_create_config() -> Result<String, Box<dyn Error>> {
-    let config_dir = dirs::config_dir().ok_or("Unable to find config directory")?;
-    let config_path = config_dir.join("gptxt.toml");
+fn print_version(verbose: bool) {
+    println!("gptxt {}", env!("CARGO_PKG_VERSION"));
+    if verbose {
+        println!("RustPython: {}", vm::version::get_version());
+        println!("Default model: {}", MODEL);
+        match config::config_path() {
+            Ok(path) => println!("Config file: {}", path.display()),
+            Err(e) => println!("Config file: unavailable ({})", e),
+        }
+    }
+}
+
+fn load_json_schema(path: &str) -> Result<JSONSchema, Box<dyn Error>> {
+    let schema: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let compiled = JSONSchema::compile(&schema).map_err(|e| e.to_string())?;
+    Ok(compiled)
+}
 
-    if !config_dir.exists() {
-        fs::create_dir_all(&config_dir)?;
+fn validate_json_flags(jsonify: bool, jsonify_one_line: bool, default_flags: &[String]) {
+    if jsonify_one_line && !jsonify {
+        print_error!("--json-one-line requires --json to be set.");
+        note_default_flags(default_flags);
+        std::process::exit(1);
     }
+}
 
-    if !config_path.exists() {
-        let mut file = File::create(&config_path)?;
-        file.write_all(br#"key = """#)?;
-        print_success!(
-            "Created a new configuration file at: {}",
-            config_path.display()
-        );
-        print_success!("Set the 'key' value in the file before using the program.");
+/// Rejects values the API would refuse with an opaque error. A temperature of 0 is allowed and
+/// means fully deterministic; token counts above a model's limit are capped later, per model.
+fn validate_generation_flags(temperature: f32, max_tokens: u16, default_flags: &[String]) {
+    if !(0.0..=MAX_TEMPERATURE).contains(&temperature) {
+        print_error!("--temp must be between 0 and {}, got {}.", MAX_TEMPERATURE, temperature);
+        note_default_flags(default_flags);
+        std::process::exit(1);
+    }
+    if max_tokens == 0 {
+        print_error!("--max-tokens must be at least 1.");
+        note_default_flags(default_flags);
         std::process::exit(1);
     }
+}
 
-    let config = fs::read_to_string(&config_path)?.parse::<Value>()?;
-
-    let key = match config.get("key") {
-        Some(key) => key.as_str().unwrap_or("").to_string(),
-        None => {
-            print_error!(
-                "The 'key' value is not set in the configuration file: {}",
-                config_path.display()
-            );
-            std::process::exit(1);
-        }
-    };
-
-    if key.is_empty() {
-        print_error!(
-            "Set the 'key' value in the configuration file before using the program: {}",
-            config_path.display()
-        );
-        std::process::exit(1);
+/// Points out the config's `default_flags` after a command-line error, since they may be its cause.
+fn note_default_flags(default_flags: &[String]) {
+    if !default_flags.is_empty() {
+        eprintln!("note: `default_flags` from the config were prepended: {}", default_flags.join(" "));
     }
+}
 
-    Ok(key)
+fn read_input_timed(args: &Arguments) -> String {
+    let started = Instant::now();
+    let input = read_input(args.input_file.as_deref(), args.max_input_bytes, args.truncate_input, args.gzip);
+    timing::record("input read", started.elapsed());
+    input
 }
 
-fn read_input(input_file: Option<&str>) -> String {
+fn read_input(input_file: Option<&str>, max_bytes: Option<u64>, truncate: bool, gzip: bool) -> String {
     match input_file {
-        Some(file) => read_file_input(file),
-        None => read_piped_input(),
+        Some(file) => read_file_input(file, max_bytes, truncate, gzip || file.ends_with(".gz")),
+        None => read_piped_input(max_bytes, truncate, gzip),
     }
 }
 
-fn read_file_input(file: &str) -> String {
-    let mut input = String::new();
-    if let Ok(mut file) = File::open(file) {
-        file.read_to_string(&mut input).unwrap_or_else(|e| {
-            print_error!("Error reading input file: {}", e);
-            std::process::exit(1);
-        });
-    } else {
-        print_error!("Error opening input file: {}", file);
+fn read_file_input(file: &str, max_bytes: Option<u64>, truncate: bool, gzip: bool) -> String {
+    input::read_file(Path::new(file), max_bytes, truncate, gzip).unwrap_or_else(|e| {
+        print_error!("could not read input file {}: {}", file, e);
         std::process::exit(1);
-    }
-    input
+    })
 }
 
-fn read_piped_input() -> String {
-    let mut input = String::new();
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
-    match handle.read_to_string(&mut input) {
-        Ok(_) => {}
-        Err(e) => print_error!("Error reading piped input: {}", e),
+fn read_piped_input(max_bytes: Option<u64>, truncate: bool, gzip: bool) -> String {
+    match input::read_stdin(max_bytes, truncate, gzip) {
+        Ok(input) => input,
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+            print_error!("could not read piped input: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            print_error!("could not read piped input: {}", e);
+            String::new()
+        }
     }
-    input
 }
 
 const TICK_INTERVAL: u64 = 100;
 
-async fn execute_program_loop(input: &str, args: Arguments) {
-    async fn generate_program_with_progress(args: &Arguments, input: &str) -> (String, String) {
+const TEMPERATURE_STEP: f32 = 0.1;
+
+/// Names `--var` can't use, since a program couldn't refer to them.
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in",
+    "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+];
+
+/// Input lines used for a sandboxed preview run when the prompt shows none.
+const PREVIEW_LINES: usize = 10;
+
+/// Tokens each chat message costs beyond its content, and tokens that prime the reply.
+const CHAT_MESSAGE_TOKENS: usize = 3;
+const CHAT_REPLY_TOKENS: usize = 3;
+
+/// Token limit for `--explain-error` explanations.
+const EXPLAIN_MAX_TOKENS: u16 = 256;
+
+/// Input lines sent with `--describe-input` when `--show-lines` isn't set.
+const DESCRIBE_SAMPLE_LINES: u16 = 20;
+
+/// Upper bound on the `--describe-input` sample, however long its lines are.
+const DESCRIBE_SAMPLE_BYTES: usize = 4000;
+
+/// Token limit for `--describe-input` summaries.
+const DESCRIBE_MAX_TOKENS: u16 = 256;
+
+/// Output lines shown per file by `--validate-against`.
+const VALIDATION_PREVIEW_LINES: usize = 3;
+
+/// Exit code used when `--strict-result` rejects the program's `result`.
+const EXIT_STRICT_RESULT: i32 = 3;
+
+/// Exit code used when `--diff-against` finds the result differs from the expected output.
+const EXIT_RESULT_MISMATCH: i32 = 5;
+
+/// Exit code used when `--warnings-as-errors` fails an otherwise successful run.
+const EXIT_WARNINGS: i32 = 6;
+
+/// Exit code used when the user quits without a program having run successfully.
+const EXIT_DECLINED: i32 = 4;
+
+/// Exit code used when the run is interrupted from the keyboard, as shells report SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Generates and runs programs for `args.task` until one succeeds or the user gives up. Continues
+/// with the arguments and result if the user starts a new task on the result, and otherwise breaks
+/// with the code to exit with.
+async fn execute_program_loop(input: &str, mut args: Arguments) -> ControlFlow<i32, (Arguments, String)> {
+    /// Generates with `model`, falling back to the other `--model` entries in order if it fails.
+    /// `model` is updated to whichever model produced the program.
+    /// With `failure`, the model is shown the failed program and its error so it can fix it.
+    /// `None` once every model has failed and the error has been printed.
+    async fn generate_program_with_progress(
+        args: &Arguments,
+        input: &str,
+        model: &mut String,
+        failure: Option<&Failure<'_>>,
+    ) -> Option<Generation> {
+        let mut candidates = vec![model.clone()];
+        candidates.extend(args.models.iter().filter(|m| *m != model).cloned());
+
+        for (i, candidate) in candidates.iter().enumerate() {
+            let message = if candidates.len() > 1 {
+                format!("Generating program with {} (attempt {}/{})...", candidate, i + 1, candidates.len())
+            } else {
+                "Generating program...".to_owned()
+            };
+            let pb = if args.quiet_api { ProgressBar::hidden() } else { spinner(args, &message) };
+            let mut attempt = 0;
+            let generation = loop {
+                let generation = generate_program(&args.generate_options(), candidate, input, failure).await;
+                match &generation {
+                    Err(e) if attempt < args.retries && is_retryable_api_error(e.as_ref()) => {
+                        attempt += 1;
+                        pb.set_message(format!("Retrying ({}/{})...", attempt, args.retries));
+                        tokio::time::sleep(retry_delay(attempt)).await;
+                    }
+                    _ => break generation,
+                }
+            };
+            pb.finish_and_clear();
+
+            match generation {
+                Ok(mut generation) => {
+                    if let Some(command) = &args.format_program {
+                        match util::filter_through_command(command, &generation.program) {
+                            Ok(formatted) => generation.program = formatted.trim().to_owned(),
+                            Err(e) => print_warn!("--format-program '{}' failed; using the program as generated: {}", command, e),
+                        }
+                    }
+                    if i > 0 {
+                        if !args.quiet_api {
+                            print_progress!("generated program with fallback model {}", candidate);
+                        }
+                        *model = candidate.clone();
+                    }
+                    return Some(generation);
+                }
+                Err(e) => match candidates.get(i + 1) {
+                    Some(next) if !is_fatal_api_error(e.as_ref()) => {
+                        if !args.quiet_api {
+                            print_warn!("model {} failed: {}; trying {}", candidate, e, next);
+                        }
+                    }
+                    _ => {
+                        match keys::current_label() {
+                            // With several keys, say which one failed so a bad one can be found.
+                            Some(key) if is_fatal_api_error(e.as_ref()) => {
+                                print_error!("OpenAI API call failed with key {}: {}", key, e)
+                            }
+                            _ => print_error!("OpenAI API call failed: {}", e),
+                        }
+                        return None;
+                    }
+                },
+            }
+        }
+
+        unreachable!("the last candidate either succeeds or returns")
+    }
+
+    /// `None` if the editor failed and the error has been printed.
+    fn write_program_manually(args: &Arguments) -> Option<Generation> {
+        let template = format!("{}\n# {}:\n", system_message(&args.generate_options()), args.task);
+        match edit_program(&template, args.editor.as_deref(), args.temp_dir.as_deref(), !args.no_alt_screen) {
+            Ok(program) => Some(Generation {
+                prompt: String::new(),
+                program,
+                usage: None,
+            }),
+            Err(e) => {
+                print_error!("could not edit program: {}", e);
+                None
+            }
+        }
+    }
+
+    fn spinner(args: &Arguments, message: &str) -> ProgressBar {
+        if args.no_progress || !(util::using_prompt_tty() || stderr().is_terminal()) {
+            return ProgressBar::hidden();
+        }
         let pb = ProgressBar::new_spinner();
-        pb.set_message("Generating program...".cyan().to_string());
+        if let Some(target) = util::prompt_tty_draw_target() {
+            pb.set_draw_target(target);
+        }
+        pb.set_message(accent(message).to_string());
         pb.enable_steady_tick(Duration::from_millis(TICK_INTERVAL));
-        let (prompt, program) = generate_program(
-            &args.task,
-            args.temperature,
-            args.max_tokens,
-            args.jsonify,
-            args.jsonify_one_line,
-            args.show_lines,
-            input,
-        )
-            .await
-            .unwrap_or_else(|e| {
-                print_error!("Error calling OpenAI API: {}", e);
-                std::process::exit(1);
-            });
-        pb.finish_and_clear();
-        (prompt, program)
+        pb
     }
 
     fn prompt_for_program_run() -> char {
-        prompt(format!("{} ([{}]es/[{}]uit/[{}]egen/[{}]dit) ",
-                       "Run program?".bold().cyan(),
-                       "y".bold(), "q".bold(), "r".bold(), "e".bold()
-        ).as_str())
+        prompt(format!("{} ([{}]es/[{}]uit/[{}]egen/[{}]dit/[{}]review) ",
+                       heading("Run program?"),
+                       emphasis("y"), emphasis("q"), emphasis("r"), emphasis("e"), emphasis("p")
+        ).as_str(), &['y', 'q', 'r', 'e', 'p'])
+    }
+
+    fn prompt_for_program_regen(temperature: f32, model: &str) -> char {
+        ui_println!();
+        prompt(format!("{} ([{}]egen/[{}]uit/[{}]dit/[{}/{}] temp {:.2}/[{}]odel {}) ",
+                       heading("Regenerate program and try again?"),
+                       emphasis("r"), emphasis("q"), emphasis("e"),
+                       emphasis("+"), emphasis("-"), temperature, emphasis("m"), model
+        ).as_str(), &['r', 'q', 'e', '+', '-', 'm'])
     }
 
-    fn prompt_for_program_regen() -> char {
-        eprintln!();
-        prompt(format!("{} ([{}]egen/[{}]uit/[{}]dit) ",
-                       "Regenerate program and try again?".bold().cyan(),
-                       "r".bold(), "q".bold(), "e".bold()
-        ).as_str())
+    fn prompt_for_program_choice(first: &str, second: &str) -> char {
+        ui_println!();
+        prompt(format!("{} ([{}] {}/[{}] {}/[{}]uit) ",
+                       heading("Use which program?"),
+                       emphasis("1"), first, emphasis("2"), second, emphasis("q")
+        ).as_str(), &['1', '2', 'q'])
     }
 
     fn show_prompt(show_prompt: bool, prompt: &str) {
         if show_prompt {
-            print_progress!("Prompt:");
-            eprintln!("------------------------------");
-            eprintln!("{}", prompt);
-            eprintln!("------------------------------");
-            eprintln!();
+            print_progress!("prompt:");
+            ui_println!("------------------------------");
+            ui_println!("{}", prompt);
+            ui_println!("------------------------------");
+            ui_println!();
         }
     }
 
+    fn show_compared_program(n: u8, model: &str, generation: &Generation) {
+        match &generation.usage {
+            Some(usage) => print_progress!(
+                "[{}] program from {} ({} prompt + {} completion tokens):",
+                n, model, usage.prompt_tokens, usage.completion_tokens
+            ),
+            None => print_progress!("[{}] program from {}:", n, model),
+        }
+        ui_println!("------------------------------");
+        ui_println!("{}", generation.program);
+        ui_println!("------------------------------");
+    }
+
+    fn show_program_diff(before: &str, after: &str) {
+        print_progress!("changes:");
+        ui_println!("------------------------------");
+        util::print_diff(before, after);
+        ui_println!("------------------------------");
+        ui_println!();
+    }
+
+    fn show_failed_program(program: &str, error: &str) {
+        let error_line = error_line_number(error);
+        print_progress!("failed program:");
+        ui_println!("------------------------------");
+        for (i, line) in program.lines().enumerate() {
+            if error_line == Some(i + 1) {
+                ui_println!("{}", alert(format!("{:>3} > {}", i + 1, line)));
+            } else {
+                ui_println!("{:>3} | {}", i + 1, line);
+            }
+        }
+        ui_println!("------------------------------");
+    }
+
     fn show_generated_program(program: &str, edited: &mut bool) {
         if !*edited {
-            print_progress!("Generated program:");
+            print_progress!("generated program:");
         } else {
-            print_progress!("Edited program:");
+            print_progress!("edited program:");
             *edited = false;
         }
-        eprintln!("------------------------------");
-        eprintln!("{}", program);
-        eprintln!("------------------------------");
+        ui_println!("------------------------------");
+        ui_println!("{}", program);
+        ui_println!("------------------------------");
+    }
+
+    async fn show_error_explanation(
+        args: &Arguments,
+        model: &str,
+        program: &str,
+        error: &str,
+        explanations: &mut HashMap<String, String>,
+    ) {
+        if !explanations.contains_key(program) {
+            let pb = spinner(args, "Explaining error...");
+            let explanation = explain_error(args, model, program, error).await;
+            pb.finish_and_clear();
+            match explanation {
+                Ok(explanation) => {
+                    explanations.insert(program.to_owned(), explanation);
+                }
+                Err(e) => {
+                    print_error!("could not explain error: {}", e);
+                    return;
+                }
+            }
+        }
+
+        ui_println!();
+        print_progress!("explanation:");
+        ui_println!("------------------------------");
+        ui_println!("{}", explanations[program]);
+        ui_println!("------------------------------");
+    }
+
+    /// Warns when the program might not set `result_var`. Advisory only; the program can still be run.
+    fn lint_program(program: &str, result_var: &str) {
+        // The output wrappers assign the result unconditionally, which would hide the program's own assignments.
+        let own_code = program.split(OUTPUT_WRAPPER_IMPORT).next().unwrap_or(program);
+        match lint::result_assignment(own_code, result_var) {
+            Some(ResultAssignment::Conditional) => print_warn!(
+                "the program only assigns `{}` inside a branch, loop, or handler; it may not be set when run",
+                result_var
+            ),
+            Some(ResultAssignment::Never) => print_warn!("the program never assigns `{}`", result_var),
+            // A program that doesn't parse fails with a clearer error when it's run.
+            Some(ResultAssignment::Always) | None => {}
+        }
+    }
+
+    async fn validate_program(args: &Arguments, program: &str) {
+        print_progress!("validation:");
+        ui_println!("------------------------------");
+        for path in &args.validate_against {
+            let input = match fs::read_to_string(path) {
+                Ok(input) => input,
+                Err(e) => {
+                    ui_println!("{} {}: could not read file: {}", alert("[failed]"), path.display(), e);
+                    continue;
+                }
+            };
+            match execute_with_checks(args, &input, program).await {
+                Ok(v) => {
+                    ui_println!("{} {} ({} lines)", accent("[ok]"), path.display(), v.lines().count());
+                    for line in v.lines().take(VALIDATION_PREVIEW_LINES) {
+                        ui_println!("    {}", line);
+                    }
+                    if v.lines().count() > VALIDATION_PREVIEW_LINES {
+                        ui_println!("    ...");
+                    }
+                }
+                Err(e) => ui_println!("{} {}: {}", alert("[failed]"), path.display(), e),
+            }
+        }
+        ui_println!("------------------------------");
+        ui_println!();
+    }
+
+    fn is_duplicate(program: &str, program_hist: &[String], history: &Option<ProgramHistory>) -> bool {
+        program_hist.iter().any(|p| p == program)
+            || history.as_ref().is_some_and(|h| h.contains(program))
+    }
+
+    /// With `--dedupe-history`, the programs `model` generated for the task in earlier sessions.
+    fn load_history(args: &Arguments, model: &str) -> Option<ProgramHistory> {
+        if !args.dedupe_history {
+            return None;
+        }
+        match ProgramHistory::load(&args.task, model) {
+            Ok(h) => Some(h),
+            Err(e) => {
+                print_error!("could not load program history: {}", e);
+                None
+            }
+        }
+    }
+
+    /// History is kept per model, so it's reloaded once `model` differs from the one it was
+    /// loaded for, whether the user switched models or generation fell back to another.
+    fn follow_model(args: &Arguments, model: &str, history_model: &mut String, history: &mut Option<ProgramHistory>) {
+        if history_model != model {
+            *history = load_history(args, model);
+            *history_model = model.to_owned();
+        }
+    }
+
+    fn record_program(program: &str, program_hist: &mut Vec<String>, history: &mut Option<ProgramHistory>) {
+        program_hist.push(program.to_owned());
+        if let Some(h) = history {
+            if let Err(e) = h.push(program) {
+                print_error!("could not save program history: {}", e);
+            }
+        }
     }
 
     //
 
-    let (prompt, mut program) = generate_program_with_progress(&args, input).await;
-    let mut program_hist = vec![program.clone()];
-    let mut edited = false;
-    show_prompt(args.show_prompt, &prompt);
+    let mut model = args.models[0].clone();
+
+    let generation = match &args.compare {
+        _ if args.manual => write_program_manually(&args),
+        None => generate_program_with_progress(&args, input, &mut model, None).await,
+        Some(other) => {
+            let mut other = other.clone();
+            let Some(first) = generate_program_with_progress(&args, input, &mut model, None).await else {
+                return ControlFlow::Break(1);
+            };
+            let Some(second) = generate_program_with_progress(&args, input, &mut other, None).await else {
+                return ControlFlow::Break(1);
+            };
+            show_compared_program(1, &model, &first);
+            show_compared_program(2, &other, &second);
+            match prompt_for_program_choice(&model, &other) {
+                '1' => Some(first),
+                '2' => {
+                    model = other;
+                    Some(second)
+                }
+                _ => return ControlFlow::Break(EXIT_DECLINED),
+            }
+        }
+    };
+    let Some(Generation { mut prompt, mut program, .. }) = generation else {
+        return ControlFlow::Break(1);
+    };
+
+    // Loaded once the program is chosen, so history is kept under the model that produced it.
+    let mut history = load_history(&args, &model);
+    let mut history_model = model.clone();
+    let mut program_hist = Vec::new();
+    let mut explanations = HashMap::new();
+    record_program(&program, &mut program_hist, &mut history);
+    let mut edited = args.manual;
+    let mut run_confirmed = false;
+    let mut validated: Option<String> = None;
+    let mut linted: Option<String> = None;
+    show_prompt(args.show_prompt && !args.manual && !args.quiet_api, &prompt);
+    if args.verbose && !args.manual {
+        print_progress!("reproduce with:");
+        ui_println!("{}", reproduction_command(&args, &model));
+        if args.system_append.is_some() {
+            ui_println!("(with the system_append from your config, which this command doesn't set)");
+        }
+        ui_println!();
+    }
+
+    // Written for every new program, whether or not it's run, so a declined one can be reported.
+    let mut bundled: Option<String> = None;
+    if let Some(path) = &args.bundle {
+        write_bundle(path, &args, &model, &prompt, &program, input);
+        bundled = Some(program.clone());
+    }
+
+    if args.dry_run {
+        if args.lint_program {
+            lint_program(&program, &args.result_var);
+        }
+        show_generated_program(&program, &mut edited);
+        // Piped or redirected, the bare program goes to STDOUT in place of a result.
+        if !stdout().is_terminal() {
+            println!("{}", program);
+        }
+        if let Some(path) = &args.export {
+            write_python_export(path, &args, &program);
+        }
+        if let Some(path) = &args.export_sh {
+            write_shell_export(path, &args, &program);
+        }
+        return ControlFlow::Break(0);
+    }
 
     //
 
     'outer: loop {
-        show_generated_program(&program, &mut edited);
+        if let Some(path) = &args.bundle {
+            if bundled.as_ref() != Some(&program) {
+                write_bundle(path, &args, &model, &prompt, &program, input);
+                bundled = Some(program.clone());
+            }
+        }
+
+        // --quiet-api still shows a program the user edited themselves.
+        if !args.quiet_on_success && (!args.quiet_api || edited) {
+            show_generated_program(&program, &mut edited);
+        }
+
+        if args.lint_program && linted.as_ref() != Some(&program) {
+            lint_program(&program, &args.result_var);
+            linted = Some(program.clone());
+        }
+
+        if !args.validate_against.is_empty() && validated.as_ref() != Some(&program) {
+            validate_program(&args, &program).await;
+            validated = Some(program.clone());
+        }
+
+        // With --remember-choice, a 'y' carries over to later programs until the user quits.
+        let choice = if args.yes {
+            'y'
+        } else if run_confirmed {
+            ui_println!("{} {}", heading("Run program?"), emphasis("y (remembered)"));
+            'y'
+        } else {
+            prompt_for_program_run()
+        };
 
-        match prompt_for_program_run() {
+        match choice {
             'y' => {
-                eprintln!();
-                match execute_program(input, &program).await {
+                run_confirmed = args.remember_choice;
+                ui_println!();
+                if let Some(path) = &args.export {
+                    write_python_export(path, &args, &program);
+                }
+                if let Some(path) = &args.export_sh {
+                    write_shell_export(path, &args, &program);
+                }
+                let pb = spinner(&args, "Executing program...");
+                let result = run_program(&args, input, &program).await;
+                pb.finish_and_clear();
+                match result {
                     Ok(v) => {
-                        println!("{}", v);
+                        if let Err(code) = print_result(&args, &v).and_then(|_| check_expected_result(&args, &v)) {
+                            return ControlFlow::Break(code);
+                        }
+                        if let Some(task) = post_run_menu(&args, &v) {
+                            args.task = task;
+                            return ControlFlow::Continue((args, v));
+                        }
                         break;
                     }
                     Err(e) => {
                         print_error!("{}", e);
+                        if args.strict_result && e.is_strict_failure() {
+                            return ControlFlow::Break(EXIT_STRICT_RESULT);
+                        }
+                        // One attempt only; there's nobody to answer the regen prompt.
+                        if args.yes {
+                            return ControlFlow::Break(1);
+                        }
+                        ui_println!();
+                        show_failed_program(&program, &e.to_string());
+                        if args.explain_error {
+                            show_error_explanation(&args, &model, &program, &e.to_string(), &mut explanations).await;
+                        }
                         loop {
-                            match prompt_for_program_regen() {
+                            match prompt_for_program_regen(args.temperature, &model) {
                                 'r' => {
-                                    (_, program) = generate_program_with_progress(&args, input).await;
-                                    if program_hist.contains(&program) {
-                                        print_error!("Re-generated program is identical to previously generated program. Please rephrase your task.");
-                                        break 'outer;
+                                    let error = e.to_string();
+                                    let failure = Failure { program: &program, error: &error };
+                                    let Some(generation) = generate_program_with_progress(&args, input, &mut model, Some(&failure)).await else {
+                                        return ControlFlow::Break(1);
+                                    };
+                                    prompt = generation.prompt;
+                                    program = generation.program;
+                                    follow_model(&args, &model, &mut history_model, &mut history);
+                                    if is_duplicate(&program, &program_hist, &history) {
+                                        print_error!("re-generated program is identical to previously generated program. Please rephrase your task.");
+                                        return ControlFlow::Break(1);
                                     } else {
-                                        program_hist.push(program.clone());
+                                        record_program(&program, &mut program_hist, &mut history);
                                         continue 'outer;
                                     }
                                 }
                                 'e' => {
-                                    eprintln!();
-                                    match edit_program_with_vi(&program) {
+                                    ui_println!();
+                                    match edit_program(&program, args.editor.as_deref(), args.temp_dir.as_deref(), !args.no_alt_screen) {
                                         Ok(edited_program) => {
+                                            if args.show_diff_on_edit {
+                                                show_program_diff(&program, &edited_program);
+                                            }
                                             program = edited_program;
                                             edited = true;
                                             continue 'outer;
                                         }
                                         Err(e) => {
-                                            eprintln!();
-                                            print_error!("Error editing program with 'vi': {}", e);
+                                            ui_println!();
+                                            print_error!("could not edit program: {}", e);
                                         }
                                     }
                                 }
-                                'q' => break 'outer,
+                                '+' => {
+                                    args.temperature = (args.temperature + TEMPERATURE_STEP).min(MAX_TEMPERATURE);
+                                }
+                                '-' => {
+                                    // 0 is as low as --temp goes, so the prompt doesn't stop short of it.
+                                    args.temperature = (args.temperature - TEMPERATURE_STEP).max(0.0);
+                                }
+                                'm' => {
+                                    model = next_regen_model(&args, &model);
+                                    follow_model(&args, &model, &mut history_model, &mut history);
+                                }
+                                'q' => return ControlFlow::Break(EXIT_DECLINED),
                                 _ => {
-                                    print_error!("Invalid input; enter 'r', 'q', or 'e'.");
+                                    print_error!("invalid input; enter 'r', 'q', 'e', '+', '-', or 'm'.");
                                     continue;
                                 }
                             }
@@ -356,52 +1872,410 @@ async fn execute_program_loop(input: &str, args: Arguments) {
                 }
             }
             'r' => {
-                eprintln!();
-                (_, program) = generate_program_with_progress(&args, input).await;
-                if program_hist.contains(&program) {
-                    print_error!("Re-generated program is identical to previously generated program. Please rephrase your task.");
-                    break;
+                ui_println!();
+                let Some(generation) = generate_program_with_progress(&args, input, &mut model, None).await else {
+                    return ControlFlow::Break(1);
+                };
+                prompt = generation.prompt;
+                program = generation.program;
+                follow_model(&args, &model, &mut history_model, &mut history);
+                if is_duplicate(&program, &program_hist, &history) {
+                    print_error!("re-generated program is identical to previously generated program. Please rephrase your task.");
+                    return ControlFlow::Break(1);
                 } else {
-                    program_hist.push(program.clone());
+                    record_program(&program, &mut program_hist, &mut history);
                 }
             }
             'e' => {
-                eprintln!();
-                match edit_program_with_vi(&program) {
+                ui_println!();
+                match edit_program(&program, args.editor.as_deref(), args.temp_dir.as_deref(), !args.no_alt_screen) {
                     Ok(edited_program) => {
+                        if args.show_diff_on_edit {
+                            show_program_diff(&program, &edited_program);
+                        }
                         program = edited_program;
                         edited = true;
                     }
                     Err(e) => {
-                        eprintln!();
-                        print_error!("Error editing program with 'vi': {}", e);
+                        ui_println!();
+                        print_error!("could not edit program: {}", e);
                     }
                 }
             }
-            'q' => break,
+            'p' => {
+                ui_println!();
+                // The sample the model was shown, so the preview runs on data it was written for.
+                let (sample, described) = match prompt_sample(&args.generate_options(), input) {
+                    Some(lines) => (lines.join("\n"), "the input lines shown in the prompt".to_owned()),
+                    None => {
+                        let lines = input.lines().take(PREVIEW_LINES).collect::<Vec<&str>>();
+                        (lines.join("\n"), format!("first {} lines of input", PREVIEW_LINES))
+                    }
+                };
+                let allowed_modules = sandbox::allowed_modules(&args.allow_modules);
+                let options = ExecOptions {
+                    strict: args.strict_result,
+                    sandbox: Some(&allowed_modules),
+                    result_hook: args.result_hook.as_deref(),
+                    result_var: Some(&args.result_var),
+                    vars: &args.vars,
+                    dump_scope: args.dump_scope,
+                    stdout_path: None,
+                    timeout: args.timeout,
+                };
+                match execute_program(&sample, &program, &options).await {
+                    Ok(v) => {
+                        print_progress!("preview result (sandboxed, {}):", described);
+                        ui_println!("------------------------------");
+                        ui_println!("{}", v);
+                        ui_println!("------------------------------");
+                    }
+                    Err(e) => print_error!("{}", e),
+                }
+                ui_println!();
+            }
+            'q' => return ControlFlow::Break(EXIT_DECLINED),
             _ => {
-                print_error!("Invalid input; enter 'y', 'q', 'r', or 'e'.");
+                print_error!("invalid input; enter 'y', 'q', 'r', 'e', or 'p'.");
                 continue;
             }
         }
     }
+
+    ControlFlow::Break(0)
+}
+
+/// A shell command that regenerates the program with the same prompt and parameters.
+fn reproduction_command(args: &Arguments, model: &str) -> String {
+    let mut command = vec!["gptxt".to_owned(), util::shell_quote(&args.task)];
+
+    command.push(format!("--model {}", util::shell_quote(model)));
+    command.push(format!("--temp {}", args.temperature));
+    command.push(format!("--max-tokens {}", args.max_tokens));
+    if let Some(n) = args.show_lines {
+        command.push(format!("--show-lines {}", n));
+    }
+    if let Some((head, tail)) = args.show_head_tail {
+        command.push(format!("--show-head-tail {}:{}", head, tail));
+    }
+    if let Some(n) = args.tail_lines {
+        command.push(format!("--tail-lines {}", n));
+    }
+    if let Some(n) = args.sample_lines {
+        command.push(format!("--sample-lines {} --seed {}", n, args.seed));
+    }
+    if args.result_var != RESULT_VAR {
+        command.push(format!("--result-var {}", args.result_var));
+    }
+    for (name, value) in &args.vars {
+        command.push(format!("--var {}", util::shell_quote(&format!("{}={}", name, value))));
+    }
+    if args.squash_blank_lines {
+        command.push("--squash-blank-lines".to_owned());
+    }
+    if args.jsonify {
+        command.push("--json".to_owned());
+    }
+    if args.jsonify_one_line {
+        command.push("--json-one-line".to_owned());
+    }
+    if let Some(dir) = &args.split_output {
+        command.push(format!("--split-output {}", util::shell_quote(&dir.display().to_string())));
+    }
+    if let Some(path) = &args.system_file {
+        command.push(format!("--system-file {}", util::shell_quote(&path.display().to_string())));
+    }
+    if args.no_system_stdin_line {
+        command.push("--no-system-stdin-line".to_owned());
+    }
+    for instruction in &args.instructions {
+        command.push(format!("--instruct {}", util::shell_quote(instruction)));
+    }
+    if let Some(file) = &args.input_file {
+        command.push(format!("--input {}", util::shell_quote(file)));
+    }
+
+    command.join(" ")
+}
+
+/// Runs `program` and applies any result checks and output side effects.
+async fn run_program(args: &Arguments, input: &str, program: &str) -> Result<String, ExecuteError> {
+    let mut result = execute_with_checks(args, input, program).await;
+    if let Some(dir) = &args.split_output {
+        result = result.and_then(|v| output::write_split_output(dir, &v, args.line_endings).map(|_| v));
+    }
+    result
+}
+
+/// Runs `program` and applies result checks, without any output side effects.
+async fn execute_with_checks(args: &Arguments, input: &str, program: &str) -> Result<String, ExecuteError> {
+    let allowed_modules = args.safe.then(|| sandbox::allowed_modules(&args.allow_modules));
+    let options = ExecOptions {
+        strict: args.strict_result,
+        sandbox: allowed_modules.as_deref(),
+        result_hook: args.result_hook.as_deref(),
+        result_var: Some(&args.result_var),
+        vars: &args.vars,
+        dump_scope: args.dump_scope,
+        stdout_path: args.separate_stdout.as_deref(),
+        timeout: args.timeout,
+    };
+    // A cached result can't reproduce the scope dump, so --dump-scope always runs the program.
+    let mut result = if args.exec_cache && !args.dump_scope {
+        exec_cache::execute_program(input, program, &options, args.verbose).await
+    } else {
+        execute_program(input, program, &options).await
+    };
+    if let Some(schema) = &args.json_schema {
+        result = result.and_then(|v| output::validate_json_schema(schema, &v).map(|_| v));
+    }
+    result
+}
+
+/// Runs `program` without any prompting and returns the code to exit with.
+async fn run_program_once(args: &Arguments, input: &str, program: &str) -> i32 {
+    let code = match run_program(args, input, program).await {
+        Ok(v) => match print_result(args, &v).and_then(|_| check_expected_result(args, &v)) {
+            Ok(()) => 0,
+            Err(code) => code,
+        },
+        Err(e) => {
+            print_error!("{}", e);
+            if args.strict_result && e.is_strict_failure() { EXIT_STRICT_RESULT } else { 1 }
+        }
+    };
+    timing::print_report();
+    finish_run(args.verbose, args.warnings_as_errors);
+    code
+}
+
+fn write_bundle(path: &Path, args: &Arguments, model: &str, prompt: &str, program: &str, input: &str) {
+    let bundle = Bundle {
+        task: args.task.clone(),
+        model: model.to_owned(),
+        temperature: args.temperature,
+        max_tokens: args.max_tokens,
+        prompt: prompt.to_owned(),
+        program: program.to_owned(),
+        input: input.to_owned(),
+        options: Some(args.run_options()),
+    };
+    match bundle.write(path) {
+        Ok(()) => print_success!("wrote bundle to {}", path.display()),
+        Err(e) => print_error!("could not write bundle to {}: {}", path.display(), e),
+    }
+}
+
+/// The program as a standalone Python script, for `--export` and `--export-sh`.
+fn standalone_python(args: &Arguments, program: &str) -> String {
+    let options = ExecOptions {
+        strict: args.strict_result,
+        result_hook: args.result_hook.as_deref(),
+        result_var: Some(&args.result_var),
+        vars: &args.vars,
+        ..Default::default()
+    };
+    export::standalone_python(&args.task, program, &options, args.line_endings, args.split_output.as_deref())
+}
+
+fn write_python_export(path: &Path, args: &Arguments, program: &str) {
+    let python = standalone_python(args, program);
+    match export::write_executable(path, &python) {
+        Ok(()) => print_success!("wrote Python script to {}", path.display()),
+        Err(e) => print_error!("could not write Python script to {}: {}", path.display(), e),
+    }
+}
+
+fn write_shell_export(path: &Path, args: &Arguments, program: &str) {
+    let python = standalone_python(args, program);
+    // The script may be run from anywhere, so the recorded input is pinned to where it is now.
+    let input_file = args.input_file.as_deref().map(|file| {
+        fs::canonicalize(file).map(|p| p.display().to_string()).unwrap_or_else(|_| file.to_owned())
+    });
+    let written = export::shell_script(&args.task, &python, input_file.as_deref(), args.output.as_deref())
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|script| export::write_executable(path, &script));
+    match written {
+        Ok(()) => print_success!("wrote shell script to {}", path.display()),
+        Err(e) => print_error!("could not write shell script to {}: {}", path.display(), e),
+    }
+}
+
+/// The result as it's written out: line endings normalized and a trailing line ending added.
+fn formatted_result(args: &Arguments, result: &str) -> String {
+    format!("{}{}", util::normalize_line_endings(result, args.line_endings), args.line_endings.as_str())
+}
+
+/// Writes the result out, or returns the code to exit with if it can't be.
+fn print_result(args: &Arguments, result: &str) -> Result<(), i32> {
+    if args.split_output.is_some() {
+        return Ok(());
+    }
+
+    let result = formatted_result(args, result);
+    match &args.output {
+        Some(path) => match fs::write(path, &result) {
+            Ok(()) => print_success!("wrote result to {}", path.display()),
+            Err(e) => {
+                print_error!("could not write result to {}: {}", path.display(), e);
+                return Err(1);
+            }
+        },
+        None => match args.max_lines_output {
+            // Only an interactive terminal gets a shortened view; pipes still receive everything.
+            Some(max) if stdout().is_terminal() && result.lines().count() > max => {
+                let line_ending = args.line_endings.as_str();
+                for line in result.lines().take(max) {
+                    print!("{}{}", line, line_ending);
+                }
+                stdout().flush().unwrap();
+                print_progress!(
+                    "... ({} more lines not shown; the result itself is complete, use --output or a pipe to see it all)",
+                    result.lines().count() - max
+                );
+            }
+            _ => print!("{}", result),
+        },
+    }
+    Ok(())
+}
+
+/// With `--diff-against`, compares the result to the expected file, returning the code to exit
+/// with if they differ.
+fn check_expected_result(args: &Arguments, result: &str) -> Result<(), i32> {
+    let Some(path) = &args.diff_against else { return Ok(()) };
+
+    let expected = fs::read_to_string(path).map_err(|e| {
+        print_error!("could not read expected output {}: {}", path.display(), e);
+        1
+    })?;
+    let actual = formatted_result(args, result);
+
+    // A missing or extra final newline in the expected file isn't a difference worth failing on.
+    if expected.trim_end_matches(['\r', '\n']) == actual.trim_end_matches(['\r', '\n']) {
+        print_success!("result matches {}", path.display());
+        return Ok(());
+    }
+
+    print_error!("result differs from {}", path.display());
+    print_progress!("diff (expected -> actual):");
+    ui_println!("------------------------------");
+    util::print_diff(&expected, &actual);
+    ui_println!("------------------------------");
+    Err(EXIT_RESULT_MISMATCH)
+}
+
+/// After a successful interactive run, offers to copy, save, or pipe the result, or to start a
+/// new task on it. Returns the new task if one was entered.
+fn post_run_menu(args: &Arguments, result: &str) -> Option<String> {
+    let interactive = stdout().is_terminal() && (util::using_prompt_tty() || stderr().is_terminal());
+    if args.no_post_menu || args.yes || !interactive || args.output.is_some() || args.split_output.is_some() {
+        return None;
+    }
+
+    let result = formatted_result(args, result);
+    ui_println!();
+    let choice = prompt(format!("{} ([{}]opy/[{}]ave/[{}]ipe/[{}]ew task/[{}]uit) ",
+                                heading("Result:"),
+                                emphasis("c"), emphasis("s"), emphasis("p"), emphasis("n"), emphasis("q")
+    ).as_str(), &['c', 's', 'p', 'n', 'q']);
+
+    match choice {
+        'c' => match util::copy_to_clipboard(&result) {
+            Ok(tool) => print_success!("copied result to the clipboard with {}", tool),
+            Err(e) => print_error!("could not copy result: {}", e),
+        },
+        's' => {
+            if let Some(path) = read_line(&format!("{} ", heading("Save to:"))) {
+                match fs::write(&path, &result) {
+                    Ok(()) => print_success!("wrote result to {}", path),
+                    Err(e) => print_error!("could not write result to {}: {}", path, e),
+                }
+            }
+        }
+        'p' => {
+            if let Some(command) = read_line(&format!("{} ", heading("Pipe to:"))) {
+                match util::pipe_to_command(&command, &result) {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => print_error!("'{}' failed: {}", command, status),
+                    Err(e) => print_error!("could not run '{}': {}", command, e),
+                }
+            }
+        }
+        'n' => return read_line(&format!("{} ", heading("New task on this result:"))),
+        _ => {}
+    }
+    None
+}
+
+/// Expands `{task_slug}` and `{timestamp}` in a `default_output` path template.
+fn expand_output_template(template: &str, task: &str) -> String {
+    let slug = task
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    let slug: String = slug.chars().take(50).collect();
+    let slug = slug.trim_end_matches('-');
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    template
+        .replace("{task_slug}", slug)
+        .replace("{timestamp}", &timestamp.to_string())
 }
 
-fn edit_program_with_vi(program: &str) -> Result<String, Box<dyn Error>> {
-    let mut temp = NamedTempFile::new()?;
+/// Opens `program` in `editor` (the `editor` config key), `$VISUAL`, `$EDITOR`, or `vi`, whichever
+/// is set first, and returns the edited program.
+fn edit_program(
+    program: &str,
+    editor: Option<&str>,
+    temp_dir: Option<&Path>,
+    alt_screen: bool,
+) -> Result<String, Box<dyn Error>> {
+    // The editor may carry arguments, as in `code --wait`.
+    let editor = editor
+        .map(str::to_owned)
+        .into_iter()
+        .chain(["VISUAL", "EDITOR"].iter().filter_map(|var| env::var(var).ok()))
+        .find(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_owned());
+    let mut editor_words = editor.split_whitespace();
+    let editor_bin = editor_words.next().unwrap_or("vi");
+    // Checked before the screen is switched so a missing editor leaves the terminal as it was.
+    let editor_path = util::find_executable(editor_bin)
+        .ok_or_else(|| format!("editor '{}' not found; set $VISUAL or $EDITOR", editor_bin))?;
+
+    let mut temp = match temp_dir {
+        // Fail here, before the editor takes over the screen, if the directory isn't usable.
+        Some(dir) => NamedTempFile::new_in(dir)
+            .map_err(|e| format!("temp directory {} is not writable: {}", dir.display(), e))?,
+        None => NamedTempFile::new()?,
+    };
     temp.write_all(program.as_bytes())?;
 
-    execute!(stdout(), EnterAlternateScreen).expect("Error entering alternate screen");
-    execute!(stderr(), EnterAlternateScreen).expect("Error entering alternate screen");
+    // The editor expects a cooked terminal whether or not it gets its own screen.
+    terminal::disable_raw_mode()?;
 
-    let status = Command::new("vi").arg(temp.path()).status()?;
+    if alt_screen {
+        execute!(stdout(), EnterAlternateScreen).expect("Error entering alternate screen");
+        execute!(util::ui(), EnterAlternateScreen).expect("Error entering alternate screen");
+    }
 
-    if !status.success() {
-        return Err(format!("vi exited with an error: {}", status).into());
+    let status = Command::new(editor_path).args(editor_words).arg(temp.path()).status();
+
+    if alt_screen {
+        execute!(stdout(), LeaveAlternateScreen).expect("Error exiting alternate screen");
+        execute!(util::ui(), LeaveAlternateScreen).expect("Error exiting alternate screen");
     }
 
-    execute!(stdout(), LeaveAlternateScreen).expect("Error exiting alternate screen");
-    execute!(stderr(), LeaveAlternateScreen).expect("Error exiting alternate screen");
+    let status = status.map_err(|e| format!("could not start editor '{}': {}", editor_bin, e))?;
+    if !status.success() {
+        return Err(format!("'{}' exited with an error: {}", editor_bin, status).into());
+    }
 
     let mut prog_edit = String::new();
     temp.seek(io::SeekFrom::Start(0))?;
@@ -411,111 +2285,140 @@ fn edit_program_with_vi(program: &str) -> Result<String, Box<dyn Error>> {
     Ok(prog_edit)
 }
 
-const SYSTEM_MESSAGE: &str = "# You are part of a tool that creates Python code for text processing.
-# You should return only Python code with no comments.
-# Do not describe the code or add any additional information about the code.
-# Data to process is stored in the string variable `data`.
-# Results should be stored in the variable `result`.
+/// The model after `current` among the ones the run was configured with (`--model` or the config
+/// file, then `--compare`), which the regen prompt cycles through with `m`.
+fn next_regen_model(args: &Arguments, current: &str) -> String {
+    let mut models: Vec<&String> = args.models.iter().collect();
+    models.extend(args.compare.iter().filter(|m| !args.models.contains(m)));
+    match models.iter().position(|m| *m == current) {
+        Some(i) => models[(i + 1) % models.len()].clone(),
+        None => models[0].clone(),
+    }
+}
+
+/// Finds the program line an error refers to, preferring frames in the generated program
+/// itself over frames inside library code.
+fn error_line_number(error: &str) -> Option<usize> {
+    let parse_after = |i: usize| -> Option<usize> {
+        let digits: String = error[i..].chars().take_while(|c| c.is_ascii_digit()).collect();
+        digits.parse().ok()
+    };
 
-import sys
-data = sys.stdin.read()
-";
+    const FRAME: &str = "\"<string>\", line ";
+    error
+        .rmatch_indices(FRAME)
+        .find_map(|(i, _)| parse_after(i + FRAME.len()))
+        .or_else(|| error.rmatch_indices("line ").find_map(|(i, _)| parse_after(i + "line ".len())))
+}
 
-async fn generate_program(
-    task: &str,
-    temperature: f32,
-    max_tokens: u16,
-    jsonify: bool,
-    jsonify_one_line: bool,
-    show_lines: Option<u16>,
-    input: &str,
-) -> Result<(String, String), Box<dyn Error>> {
-    let mut prompt = SYSTEM_MESSAGE.to_owned();
+/// Prints the number of tokens in `prompt` for `model`, and with `verbose` the tokens themselves.
+fn preview_tokens(model: &str, messages: &[ChatCompletionMessage], verbose: bool) {
+    // Models tiktoken doesn't know about are assumed to use the GPT-3.5/GPT-4 encoding.
+    let bpe = tiktoken_rs::get_bpe_from_model(model)
+        .or_else(|_| tiktoken_rs::cl100k_base())
+        .unwrap_or_else(|e| {
+            print_error!("could not load a tokenizer: {}", e);
+            std::process::exit(1);
+        });
 
-    if let Some(n) = show_lines {
-        let shown_lines = input
-            .lines()
-            .take(n as usize)
-            .map(|s| format!("#>{}", s))
-            .collect::<Vec<String>>()
-            .join("\n");
+    // Each message costs a few tokens beyond its content, and the reply is primed with a few more.
+    let mut count = CHAT_REPLY_TOKENS;
+    for message in messages {
+        let tokens = bpe.split_by_token(&message.content, false).unwrap_or_else(|e| {
+            print_error!("could not tokenize the prompt: {}", e);
+            std::process::exit(1);
+        });
 
-        prompt.push_str(&format!(
-            "\n# First {} lines of `data`:\n{}\n",
-            n, shown_lines
-        ));
+        if verbose {
+            print_progress!("tokens ({}):", role_name(&message.role));
+            ui_println!("------------------------------");
+            for (i, token) in tokens.iter().enumerate() {
+                ui_println!("{:>6} {:?}", i, token);
+            }
+            ui_println!("------------------------------");
+        }
+        count += tokens.len() + CHAT_MESSAGE_TOKENS;
     }
+    println!("{} tokens ({})", count, model);
+}
 
-    prompt.push_str(&format!("\n# {}:", task));
-
-    //
+/// Asks `model` for a plain-English explanation of why `program` failed with `error`.
+async fn explain_error(
+    args: &Arguments,
+    model: &str,
+    program: &str,
+    error: &str,
+) -> Result<String, Box<dyn Error>> {
+    let prompt = format!(
+        "The following Python program was written for the task \"{}\". Running it failed.\n\n\
+         Program:\n{}\n\nError:\n{}\n\n\
+         In a few plain-English sentences, explain why the program failed:\n",
+        args.task, program, error
+    );
+
+    complete_text(args.trace.as_deref(), model, &prompt, EXPLAIN_MAX_TOKENS).await
+}
 
-    let completion = Completion::builder("text-davinci-003")
-        .prompt(&prompt)
-        .temperature(temperature)
-        .max_tokens(max_tokens)
-        .create()
-        .await?;
-
-    match completion {
-        Ok(completion_result) => {
-            let mut program = completion_result
-                .choices
-                .first()
-                .unwrap()
-                .text
-                .trim()
-                .to_owned();
-
-            if jsonify_one_line {
-                program = format!(
-                    "{}\nimport json; result = json.dumps(result, separators=(',', ':'))",
-                    program
-                );
-            } else if jsonify {
-                program = format!("{}\nimport json; result = json.dumps(result)", program);
-            }
-            Ok((prompt, program))
+/// Asks `model` to summarize the structure of a bounded sample of `input`.
+async fn describe_input(args: &Arguments, model: &str, input: &str) -> Result<String, Box<dyn Error>> {
+    let lines = args.show_lines.unwrap_or(DESCRIBE_SAMPLE_LINES) as usize;
+    let mut sample = input.lines().take(lines).collect::<Vec<&str>>().join("\n");
+    if sample.len() > DESCRIBE_SAMPLE_BYTES {
+        let mut end = DESCRIBE_SAMPLE_BYTES;
+        while !sample.is_char_boundary(end) {
+            end -= 1;
         }
-        Err(error) => Err(Box::new(error)),
+        sample.truncate(end);
     }
+
+    let prompt = format!(
+        "Here is a sample of a text input:\n\n{}\n\n\
+         Describe the structure of this input: its format, any columns or fields, \
+         and notable patterns or irregularities. Be concise.\n",
+        sample
+    );
+
+    complete_text(args.trace.as_deref(), model, &prompt, DESCRIBE_MAX_TOKENS).await
 }
 
-fn prompt(message: &str) -> char {
-    eprint!("{}", message);
-    stderr().flush().unwrap();
+fn prompt(message: &str, choices: &[char]) -> char {
+    ui_print!("{}", message);
 
     let input: char;
 
-    terminal::enable_raw_mode().unwrap();
+    // crossterm reads keys from /dev/tty when STDIN isn't a terminal, so prompts keep working when
+    // the data is piped in. Without any terminal, answers are read as lines from STDIN instead.
+    if terminal::enable_raw_mode().is_err() {
+        return prompt_from_stdin(choices);
+    }
 
     loop {
         if let Ok(true) = poll(Duration::from_millis(100)) {
+            let event = read();
+            if let Ok(Event::Resize(columns, _)) = event {
+                redraw_prompt(message, columns);
+                continue;
+            }
             if let Ok(Event::Key(KeyEvent {
                                      code, modifiers, ..
-                                 })) = read()
+                                 })) = event
             {
                 match code {
-                    KeyCode::Char(ch @ 'y') |
-                    KeyCode::Char(ch @ 'q') |
-                    KeyCode::Char(ch @ 'r') |
-                    KeyCode::Char(ch @ 'e') => {
+                    KeyCode::Char(ch) if choices.contains(&ch) => {
                         input = ch;
                         break;
                     }
                     KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
                         terminal::disable_raw_mode().unwrap();
-                        print_error!("Caught Ctrl+C; exiting.");
-                        std::process::exit(0);
+                        print_warn!("caught Ctrl+C; exiting.");
+                        std::process::exit(EXIT_INTERRUPTED);
                     }
                     KeyCode::Char('\\') if modifiers.contains(KeyModifiers::CONTROL) => {
                         terminal::disable_raw_mode().unwrap();
-                        print_error!(r#"Caught Ctrl+\; exiting."#);
-                        std::process::exit(0);
-                    }
-                    _ => {
-                        stderr().flush().unwrap();
+                        print_warn!(r#"caught Ctrl+\; exiting."#);
+                        std::process::exit(EXIT_INTERRUPTED);
                     }
+                    _ => {}
                 }
             }
         }
@@ -523,70 +2426,51 @@ fn prompt(message: &str) -> char {
 
     terminal::disable_raw_mode().unwrap();
 
-    eprintln!("{}", input);
+    ui_println!("{}", input);
     input
 }
 
-#[derive(Debug)]
-enum ExecuteError {
-    CompileError(String),
-    ExecutionError(String),
-    ResultNotFound,
-    ResultConversionError(String),
-}
-
-impl fmt::Display for ExecuteError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            ExecuteError::CompileError(err) =>
-                write!(f, "Error compiling Python program: {}", err),
-            ExecuteError::ExecutionError(err) =>
-                write!(f, "Error executing Python program: {}", err),
-            ExecuteError::ResultNotFound =>
-                write!(f, "Error: 'result' variable not found"),
-            ExecuteError::ResultConversionError(t) =>
-                write!(f, "Error: Failed to convert 'result' PyObject to a Rust String; type is: {}", t),
+/// Reads answers to a prompt as lines from STDIN, for when there's no terminal to read keys from.
+fn prompt_from_stdin(choices: &[char]) -> char {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                ui_println!();
+                print_error!("no terminal to answer the prompt on, and STDIN is exhausted");
+                // Every prompt offers 'q', so this ends the run the same way quitting does.
+                return 'q';
+            }
+            Ok(_) => {}
+        }
+        if let Some(ch) = line.trim().chars().next().filter(|ch| choices.contains(ch)) {
+            ui_println!("{}", ch);
+            return ch;
         }
     }
 }
 
-async fn execute_program(input: &str, program: &str) -> Result<String, ExecuteError> {
-    let interp = rustpython::InterpreterConfig::new()
-        .init_stdlib()
-        .interpreter();
-
-    interp.enter(|vm| {
-        let program_obj = vm
-            .compile(program, vm::compiler::Mode::Exec, "<string>".to_owned())
-            .map_err(|err| ExecuteError::CompileError(err.to_string()))?;
-
-        let scope = vm.new_scope_with_builtins();
-
-        let data_pyobj = vm.ctx.new_str(input);
-        scope
-            .locals
-            .set_item("data", PyObjectRef::from(data_pyobj), vm)
-            .expect("Failed to set variable in scope");
-
-        vm.run_code_obj(program_obj, scope.clone()).map_err(|err| {
-            let mut buf = String::new();
-            vm.write_exception(&mut buf, &err)
-                .expect("Failed to write exception");
-            ExecuteError::ExecutionError(buf)
-        })?;
-
-        let result_pyobj = scope
-            .locals
-            .get_item("result", vm)
-            .map_err(|_| ExecuteError::ResultNotFound)?;
-
-        let result_str: String = result_pyobj.clone().try_into_value(vm).map_err(|_| {
-            let n = result_pyobj.clone().class().name().to_owned();
-            ExecuteError::ResultConversionError(n)
-        })?;
+/// Reads a line typed at the terminal, even when STDIN carries the input data.
+fn read_line(message: &str) -> Option<String> {
+    ui_print!("{}", message);
+    let tty = File::open("/dev/tty").ok()?;
+    let mut line = String::new();
+    io::BufReader::new(tty).read_line(&mut line).ok()?;
+    let line = line.trim();
+    (!line.is_empty()).then(|| line.to_owned())
+}
 
-        let result_norm = result_str.replace(r#"\r"#, "\r").replace(r#"\n"#, "\n");
+/// Clears the rows `message` occupies after the terminal reflows it to `columns` and prints it again.
+fn redraw_prompt(message: &str, columns: u16) {
+    let width = util::visible_width(message).max(1);
+    let rows = width.div_ceil(columns.max(1) as usize);
 
-        Ok(result_norm)
-    })
+    let mut ui = util::ui();
+    if rows > 1 {
+        let _ = queue!(ui, cursor::MoveToPreviousLine((rows - 1) as u16));
+    }
+    let _ = queue!(ui, cursor::MoveToColumn(0), terminal::Clear(ClearType::FromCursorDown));
+    let _ = write!(ui, "{}", message);
+    let _ = ui.flush();
 }
@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::{Component, Path};
+
+use jsonschema::JSONSchema;
+
+use crate::execute::ExecuteError;
+use crate::util::{self, LineEnding};
+
+/// Checks that `result` is JSON matching `schema`, for `--json-schema`.
+pub fn validate_json_schema(schema: &JSONSchema, result: &str) -> Result<(), ExecuteError> {
+    let instance: serde_json::Value = serde_json::from_str(result)
+        .map_err(|e| ExecuteError::SchemaError(format!("result is not valid JSON: {}", e)))?;
+
+    if let Err(mut errors) = schema.validate(&instance) {
+        if let Some(error) = errors.next() {
+            let path = error.instance_path.to_string();
+            let path = if path.is_empty() { "/".to_owned() } else { path };
+            return Err(ExecuteError::SchemaError(format!("{} (at {})", error, path)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes each entry of `result`, a JSON object of file names and contents, to a file in `dir`,
+/// for `--split-output`.
+pub fn write_split_output(dir: &Path, result: &str, line_endings: LineEnding) -> Result<(), ExecuteError> {
+    let files: serde_json::Map<String, serde_json::Value> = serde_json::from_str(result)
+        .map_err(|e| ExecuteError::SplitOutputError(format!("'result' is not a dict: {}", e)))?;
+
+    // Every file is checked before any is written. Only plain relative names are allowed so
+    // nothing lands outside `dir`.
+    let mut checked = Vec::with_capacity(files.len());
+    for (name, contents) in &files {
+        let path = Path::new(name);
+        if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(ExecuteError::SplitOutputError(format!("invalid file name: {:?}", name)));
+        }
+        let Some(contents) = contents.as_str() else {
+            return Err(ExecuteError::ResultConversionError(format!("{} for key {:?}", python_type_name(contents), name)));
+        };
+        checked.push((dir.join(path), contents));
+    }
+
+    for (path, contents) in checked {
+        let contents = util::normalize_line_endings(contents, line_endings);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| ExecuteError::SplitOutputError(format!("{}: {}", parent.display(), e)))?;
+        }
+        fs::write(&path, contents)
+            .map_err(|e| ExecuteError::SplitOutputError(format!("{}: {}", path.display(), e)))?;
+    }
+
+    print_success!("wrote {} files to {}", files.len(), dir.display());
+    Ok(())
+}
+
+/// The Python type a value in the JSON form of `result` came from, for error messages.
+fn python_type_name(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "NoneType",
+        serde_json::Value::Bool(_) => "bool",
+        serde_json::Value::Number(n) if n.is_f64() => "float",
+        serde_json::Value::Number(_) => "int",
+        serde_json::Value::String(_) => "str",
+        serde_json::Value::Array(_) => "list",
+        serde_json::Value::Object(_) => "dict",
+    }
+}
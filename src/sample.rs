@@ -0,0 +1,45 @@
+/// The last `n` lines of `input`, or all of them if there are fewer.
+pub fn tail(input: &str, n: usize) -> Vec<&str> {
+    let lines = input.lines().collect::<Vec<&str>>();
+    lines[lines.len().saturating_sub(n)..].to_vec()
+}
+
+/// `n` lines of `input` picked at random and kept in their original order, or all of them if
+/// there are fewer. The same `seed` always picks the same lines.
+pub fn random(input: &str, n: usize, seed: u64) -> Vec<&str> {
+    let lines = input.lines().collect::<Vec<&str>>();
+    let n = n.min(lines.len());
+
+    // A partial Fisher-Yates shuffle: the first `n` indices end up a uniform random choice.
+    let mut indices = (0..lines.len()).collect::<Vec<usize>>();
+    let mut rng = SplitMix64(seed);
+    for i in 0..n {
+        let j = i + (rng.next() % (lines.len() - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+
+    let mut chosen = indices[..n].to_vec();
+    chosen.sort_unstable();
+    chosen.into_iter().map(|i| lines[i]).collect()
+}
+
+/// A seed for when `--seed` isn't given, taken from the clock.
+pub fn fresh_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Written out rather than taken from a crate so a seed picks the same lines in every release.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
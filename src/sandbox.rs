@@ -0,0 +1,151 @@
+/// Modules a sandboxed program may import.
+pub const DEFAULT_MODULES: &[&str] = &[
+    "bisect", "collections", "copy", "csv", "datetime", "decimal", "enum", "fractions", "functools",
+    "heapq", "itertools", "json", "math", "operator", "random", "re", "statistics", "string",
+    "textwrap", "unicodedata",
+];
+
+/// `DEFAULT_MODULES` plus any modules allowed with `--allow-module`.
+pub fn allowed_modules(extra: &[String]) -> Vec<String> {
+    DEFAULT_MODULES
+        .iter()
+        .map(|m| m.to_string())
+        .chain(extra.iter().cloned())
+        .collect()
+}
+
+/// Python code that, run in a program's scope before the program itself, loads the `allowed`
+/// modules while that's still possible and then restricts the program's own imports to them.
+/// Imports made by library modules are left alone so the allowed modules keep working. A blocked
+/// import raises `BlockedImportError` with the module name as its only argument.
+///
+/// This only keeps a program to the modules it's meant to use; what stops it from reaching files
+/// or the network is `lock_down`.
+pub fn prelude(allowed: &[String]) -> String {
+    let allowed = allowed
+        .iter()
+        .map(|m| format!("{:?}", m))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!(
+        r#"def __gptxt_sandbox(program_globals):
+    import builtins, sys
+    allowed = {{{allowed}}}
+    real_import = builtins.__import__
+
+    class BlockedImportError(ImportError):
+        pass
+
+    def guarded_import(name, globals=None, locals=None, fromlist=(), level=0):
+        # Who's importing is told by the calling frame; the `globals` argument can say anything.
+        importer = sys._getframe(1).f_globals
+        if importer is program_globals and name.split('.')[0] not in allowed:
+            raise BlockedImportError(name.split('.')[0])
+        return real_import(name, globals, locals, fromlist, level)
+
+    for name in allowed:
+        try:
+            real_import(name)
+        except ImportError:
+            pass
+
+    builtins.__import__ = guarded_import
+
+__gptxt_sandbox(globals())
+del __gptxt_sandbox
+"#
+    )
+}
+
+/// System calls a sandboxed program can't make: ones that open, create, change, or delete files,
+/// use the network, or start or signal other processes. Files and pipes that are already open,
+/// like the child's own standard streams, keep working.
+#[cfg(target_os = "linux")]
+const BLOCKED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_openat,
+    libc::SYS_openat2,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat2,
+    libc::SYS_mkdirat,
+    libc::SYS_mknodat,
+    libc::SYS_linkat,
+    libc::SYS_symlinkat,
+    libc::SYS_fchmod,
+    libc::SYS_fchmodat,
+    libc::SYS_fchown,
+    libc::SYS_fchownat,
+    libc::SYS_truncate,
+    libc::SYS_ftruncate,
+    libc::SYS_utimensat,
+    libc::SYS_chdir,
+    libc::SYS_fchdir,
+    libc::SYS_chroot,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_io_uring_setup,
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_connect,
+    libc::SYS_bind,
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_writev,
+    libc::SYS_kill,
+    libc::SYS_tkill,
+    libc::SYS_tgkill,
+];
+
+/// Older forms of `BLOCKED_SYSCALLS` that only some architectures still have.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const BLOCKED_LEGACY_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_open,
+    libc::SYS_creat,
+    libc::SYS_unlink,
+    libc::SYS_rename,
+    libc::SYS_renameat,
+    libc::SYS_mkdir,
+    libc::SYS_rmdir,
+    libc::SYS_mknod,
+    libc::SYS_link,
+    libc::SYS_symlink,
+    libc::SYS_chmod,
+    libc::SYS_chown,
+    libc::SYS_lchown,
+    libc::SYS_utimes,
+    libc::SYS_futimesat,
+    libc::SYS_fork,
+    libc::SYS_vfork,
+];
+
+#[cfg(all(target_os = "linux", not(target_arch = "x86_64")))]
+const BLOCKED_LEGACY_SYSCALLS: &[libc::c_long] = &[];
+
+/// Blocks `BLOCKED_SYSCALLS` for the rest of this process's life; they fail with `EPERM`, which
+/// Python raises as `PermissionError`. There's no undoing it, so it's only ever done in a child
+/// process made for one sandboxed run.
+#[cfg(target_os = "linux")]
+pub fn lock_down() -> Result<(), String> {
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+
+    let rules = BLOCKED_SYSCALLS
+        .iter()
+        .chain(BLOCKED_LEGACY_SYSCALLS)
+        .map(|&syscall| (syscall, Vec::new()))
+        .collect();
+    let arch: TargetArch = std::env::consts::ARCH.try_into().map_err(|e| format!("{}", e))?;
+    let filter = SeccompFilter::new(rules, SeccompAction::Allow, SeccompAction::Errno(libc::EPERM as u32), arch)
+        .map_err(|e| e.to_string())?;
+    let program: BpfProgram = filter.try_into().map_err(|e| format!("{}", e))?;
+    seccompiler::apply_filter(&program).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn lock_down() -> Result<(), String> {
+    Err("the sandbox is only available on Linux".to_owned())
+}
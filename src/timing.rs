@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STARTED: Lazy<Instant> = Lazy::new(Instant::now);
+/// Total time and number of occurrences per phase, in the order phases were first seen.
+static PHASES: Lazy<Mutex<Vec<(&'static str, Duration, u32)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+pub fn enable() {
+    Lazy::force(&STARTED);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Adds `elapsed` to the running total for `phase`. A no-op unless `--profile-timing` is set.
+pub fn record(phase: &'static str, elapsed: Duration) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut phases = PHASES.lock().unwrap();
+    match phases.iter_mut().find(|(name, ..)| *name == phase) {
+        Some((_, total, count)) => {
+            *total += elapsed;
+            *count += 1;
+        }
+        None => phases.push((phase, elapsed, 1)),
+    }
+}
+
+/// Prints each recorded phase and the total wall-clock time where prompts go (see `util::ui`).
+pub fn print_report() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    print_progress!("\ntiming:");
+    ui_println!("------------------------------");
+    for (phase, total, count) in PHASES.lock().unwrap().iter() {
+        let phase = if *count > 1 { format!("{} (x{})", phase, count) } else { phase.to_string() };
+        ui_println!("{:<24} {:>10.1?}", phase, total);
+    }
+    ui_println!("{:<24} {:>10.1?}", "total", STARTED.elapsed());
+    ui_println!("------------------------------");
+}
@@ -1,32 +1,486 @@
+use std::env;
+use std::fmt::{self, Display};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::queue;
+use crossterm::style::{style, Attribute, Color, ResetColor, SetAttribute, SetForegroundColor, StyledContent, Stylize};
+use indicatif::ProgressDrawTarget;
+use once_cell::sync::{Lazy, OnceCell};
+use similar::{ChangeTag, TextDiff};
+
+static COLOR: Lazy<AtomicBool> = Lazy::new(|| {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    AtomicBool::new(!no_color && io::stderr().is_terminal())
+});
+
+pub fn disable_color() {
+    COLOR.store(false, Ordering::Relaxed);
+}
+
+pub fn color_enabled() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+static HIGH_CONTRAST: AtomicBool = AtomicBool::new(false);
+
+/// Selects the palette for all UI styling; `mono` is the same as `--no-color`.
+pub fn set_theme(theme: &str) -> Result<(), String> {
+    match theme {
+        "default" => {}
+        "mono" => disable_color(),
+        "high-contrast" => HIGH_CONTRAST.store(true, Ordering::Relaxed),
+        other => return Err(format!("unknown theme '{}'; expected default, mono, or high-contrast", other)),
+    }
+    Ok(())
+}
+
+/// What a piece of styled output means, independent of how the theme draws it.
+#[derive(Clone, Copy)]
+enum Role {
+    Heading,
+    Accent,
+    Alert,
+    Emphasis,
+    Error,
+    Warn,
+    Success,
+    Progress,
+    Removed,
+    Added,
+}
+
+/// The color (if any) and boldness the active theme uses for `role`.
+fn palette(role: Role) -> (Option<Color>, bool) {
+    let high_contrast = HIGH_CONTRAST.load(Ordering::Relaxed);
+    let color = match role {
+        Role::Heading | Role::Accent | Role::Progress if high_contrast => Color::Yellow,
+        Role::Heading | Role::Accent | Role::Progress => Color::Cyan,
+        Role::Alert | Role::Error | Role::Removed => Color::Red,
+        Role::Warn if high_contrast => Color::Magenta,
+        Role::Warn => Color::Yellow,
+        Role::Success | Role::Added => Color::Green,
+        Role::Emphasis => return (None, true),
+    };
+    let bold = high_contrast || !matches!(role, Role::Accent | Role::Removed | Role::Added);
+    (Some(color), bold)
+}
+
+fn styled<D: Display>(content: D, role: Role) -> StyledContent<D> {
+    let s = style(content);
+    if !color_enabled() {
+        return s;
+    }
+    let (color, bold) = palette(role);
+    let s = match color {
+        Some(color) => s.with(color),
+        None => s,
+    };
+    if bold { s.bold() } else { s }
+}
+
+static PROMPT_TTY: OnceCell<File> = OnceCell::new();
+
+/// Sends interactive output to the controlling terminal, so redirecting stderr doesn't hide it.
+pub fn use_prompt_tty() -> io::Result<()> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let _ = PROMPT_TTY.set(tty);
+    Ok(())
+}
+
+pub fn using_prompt_tty() -> bool {
+    PROMPT_TTY.get().is_some()
+}
+
+/// Sink for prompts, programs, and progress: `/dev/tty` with `--prompt-tty`, stderr otherwise.
+pub fn ui() -> Box<dyn Write> {
+    match PROMPT_TTY.get() {
+        Some(tty) => Box::new(tty),
+        None => Box::new(io::stderr()),
+    }
+}
+
+/// Draw target for spinners when `--prompt-tty` is set.
+pub fn prompt_tty_draw_target() -> Option<ProgressDrawTarget> {
+    let tty = PROMPT_TTY.get()?;
+    let term = console::Term::read_write_pair(tty.try_clone().ok()?, tty.try_clone().ok()?);
+    Some(ProgressDrawTarget::term(term, 20))
+}
+
+#[derive(Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warn,
+    Success,
+    Progress,
+}
+
+impl Severity {
+    fn prefix(self) -> &'static str {
+        match self {
+            Severity::Error => "error: ",
+            Severity::Warn => "warning: ",
+            Severity::Success | Severity::Progress => "info: ",
+        }
+    }
+
+    fn role(self) -> Role {
+        match self {
+            Severity::Error => Role::Error,
+            Severity::Warn => Role::Warn,
+            Severity::Success => Role::Success,
+            Severity::Progress => Role::Progress,
+        }
+    }
+}
+
+/// Every warning emitted so far, for `--verbose` and `--warnings-as-errors`.
+static WARNINGS: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+pub fn warnings() -> Vec<String> {
+    WARNINGS.lock().unwrap().clone()
+}
+
+/// Single sink for all diagnostics; every `print_*!` macro ends up here.
+pub fn emit(severity: Severity, args: fmt::Arguments) {
+    let message = args.to_string();
+    // Leading blank lines are spacing, not part of the message, so they go before the prefix.
+    let body = message.trim_start_matches('\n');
+    let spacing = &message[..message.len() - body.len()];
+
+    if let Severity::Warn = severity {
+        WARNINGS.lock().unwrap().push(body.to_owned());
+    }
+
+    // Errors and warnings stay on stderr so they're logged; the rest is interactive output.
+    let mut out: Box<dyn Write> = match severity {
+        Severity::Error | Severity::Warn => Box::new(io::stderr().lock()),
+        Severity::Success | Severity::Progress => ui(),
+    };
+    write!(out, "{}", spacing).unwrap();
+    if color_enabled() {
+        let (color, bold) = palette(severity.role());
+        if let Some(color) = color {
+            queue!(out, SetForegroundColor(color)).unwrap();
+        }
+        if bold {
+            queue!(out, SetAttribute(Attribute::Bold)).unwrap();
+        }
+    }
+    write!(out, "{}{}", severity.prefix(), body).unwrap();
+    if color_enabled() {
+        queue!(out, ResetColor).unwrap();
+        queue!(out, SetAttribute(Attribute::Reset)).unwrap();
+    }
+    writeln!(out).unwrap();
+    out.flush().unwrap();
+}
+
+pub fn heading<D: Display>(content: D) -> StyledContent<D> {
+    styled(content, Role::Heading)
+}
+
+pub fn accent<D: Display>(content: D) -> StyledContent<D> {
+    styled(content, Role::Accent)
+}
+
+pub fn alert<D: Display>(content: D) -> StyledContent<D> {
+    styled(content, Role::Alert)
+}
+
+pub fn emphasis<D: Display>(content: D) -> StyledContent<D> {
+    styled(content, Role::Emphasis)
+}
+
+/// Number of characters `s` takes up on screen, skipping ANSI escape sequences.
+pub fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end at the first byte in '@'..='~'.
+            if chars.next() == Some('[') {
+                chars.by_ref().find(|c| ('@'..='~').contains(c));
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Prints a line diff of `old` against `new` to the interactive output.
+pub fn print_diff(old: &str, new: &str) {
+    let diff = TextDiff::from_lines(old, new);
+    let mut out = ui();
+
+    for change in diff.iter_all_changes() {
+        let (sign, role) = match change.tag() {
+            ChangeTag::Delete => ("-", Some(Role::Removed)),
+            ChangeTag::Insert => ("+", Some(Role::Added)),
+            ChangeTag::Equal => (" ", None),
+        };
+        let line = format!("{}{}", sign, change.value().trim_end_matches('\n'));
+        match role {
+            Some(role) => writeln!(out, "{}", styled(line, role)),
+            None => writeln!(out, "{}", line),
+        }
+        .unwrap();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineEnding {
+    Keep,
+    Lf,
+    Crlf,
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LineEnding, String> {
+        match s {
+            "keep" => Ok(LineEnding::Keep),
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            other => Err(format!("invalid line ending '{}'; expected keep, lf, or crlf", other)),
+        }
+    }
+}
+
+impl LineEnding {
+    /// The name `--line-endings` takes.
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Keep => "keep",
+            LineEnding::Lf => "lf",
+            LineEnding::Crlf => "crlf",
+        }
+    }
+
+    /// The terminator written after the result.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Keep | LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+pub fn normalize_line_endings(s: &str, line_endings: LineEnding) -> String {
+    match line_endings {
+        LineEnding::Keep => s.to_owned(),
+        LineEnding::Lf => s.replace("\r\n", "\n"),
+        LineEnding::Crlf => s.replace("\r\n", "\n").replace('\n', "\r\n"),
+    }
+}
+
+/// Quotes `s` for POSIX shells when it contains anything beyond a safe set of characters.
+pub fn shell_quote(s: &str) -> String {
+    let safe = !s.is_empty()
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_./,:=+@%".contains(c));
+    if safe {
+        s.to_owned()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// Replaces each `${VAR}` in `s` with the value of the environment variable `VAR`.
+pub fn expand_env_vars(s: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("unterminated '${{' in: {}", s))?;
+        let name = &after[..end];
+        let value = env::var(name)
+            .map_err(|_| format!("environment variable '{}' is not set", name))?;
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Resolves `bin` the way a shell would: paths are checked directly, bare names via `$PATH`.
+pub fn find_executable(bin: &str) -> Option<PathBuf> {
+    if bin.contains('/') {
+        let path = Path::new(bin);
+        return is_executable(path).then(|| path.to_path_buf());
+    }
+
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(bin))
+            .find(|path| is_executable(path))
+    })
+}
+
+/// How often a child given a timeout is checked for having exited.
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Clipboard tools tried in order, with the arguments that make them read STDIN.
+const CLIPBOARD_TOOLS: &[(&str, &[&str])] = &[
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+];
+
+/// Copies `text` with the first available clipboard tool, returning its name.
+pub fn copy_to_clipboard(text: &str) -> Result<&'static str, String> {
+    let (tool, args) = CLIPBOARD_TOOLS
+        .iter()
+        .find(|(tool, _)| find_executable(tool).is_some())
+        .ok_or("no clipboard tool found; install pbcopy, wl-copy, xclip, or xsel")?;
+
+    let status = write_to_process(Command::new(tool).args(*args), text).map_err(|e| e.to_string())?;
+    if !status.success() {
+        return Err(format!("{} failed: {}", tool, status));
+    }
+    Ok(*tool)
+}
+
+/// Runs `command` with `sh -c`, passing `text` on its STDIN.
+pub fn pipe_to_command(command: &str, text: &str) -> io::Result<ExitStatus> {
+    write_to_process(Command::new("sh").arg("-c").arg(command), text)
+}
+
+/// Runs `command` with `sh -c` on `text` and returns what it writes to STDOUT, or its STDERR if it fails.
+pub fn filter_through_command(command: &str, text: &str) -> Result<String, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // Written from another thread so a command that streams output before reading all of its
+    // input can't deadlock against us.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = text.to_owned();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(if stderr.is_empty() { output.status.to_string() } else { stderr });
+    }
+    String::from_utf8(output.stdout).map_err(|e| e.to_string())
+}
+
+/// Like `Child::wait_with_output`, but kills the child and returns `None` once `timeout` passes.
+pub fn wait_with_timeout(mut child: Child, timeout: Duration) -> io::Result<Option<Output>> {
+    // Drained on their own threads so a chatty program can't block on a full pipe.
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(WAIT_INTERVAL);
+    };
+
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+fn write_to_process(command: &mut Command, text: &str) -> io::Result<ExitStatus> {
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    child.wait()
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[macro_export]
 macro_rules! print_error {
-    ($($arg:tt)*) => {{
-        let mut stderr = std::io::stderr();
-        queue!(stderr, crossterm::style::SetForegroundColor(crossterm::style::Color::Red)).unwrap();
-        queue!(stderr, crossterm::style::SetAttribute(crossterm::style::Attribute::Bold)).unwrap();
-        eprintln!($($arg)*);
-        queue!(stderr, crossterm::style::ResetColor).unwrap();
-        queue!(stderr, crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)).unwrap();
-    }};
+    ($($arg:tt)*) => {
+        $crate::util::emit($crate::util::Severity::Error, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! print_warn {
+    ($($arg:tt)*) => {
+        $crate::util::emit($crate::util::Severity::Warn, format_args!($($arg)*))
+    };
 }
 
+#[macro_export]
 macro_rules! print_success {
+    ($($arg:tt)*) => {
+        $crate::util::emit($crate::util::Severity::Success, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! print_progress {
+    ($($arg:tt)*) => {
+        $crate::util::emit($crate::util::Severity::Progress, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! ui_print {
     ($($arg:tt)*) => {{
-        let mut stderr = std::io::stderr();
-        queue!(stderr, crossterm::style::SetForegroundColor(crossterm::style::Color::Green)).unwrap();
-        queue!(stderr, crossterm::style::SetAttribute(crossterm::style::Attribute::Bold)).unwrap();
-        eprintln!($($arg)*);
-        queue!(stderr, crossterm::style::ResetColor).unwrap();
-        queue!(stderr, crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)).unwrap();
+        use std::io::Write as _;
+        let mut ui = $crate::util::ui();
+        let _ = write!(ui, $($arg)*);
+        let _ = ui.flush();
     }};
 }
 
-macro_rules! print_progress {
+#[macro_export]
+macro_rules! ui_println {
     ($($arg:tt)*) => {{
-        let mut stderr = std::io::stderr();
-        queue!(stderr, crossterm::style::SetForegroundColor(crossterm::style::Color::Cyan)).unwrap();
-        queue!(stderr, crossterm::style::SetAttribute(crossterm::style::Attribute::Bold)).unwrap();
-        eprintln!($($arg)*);
-        queue!(stderr, crossterm::style::ResetColor).unwrap();
-        queue!(stderr, crossterm::style::SetAttribute(crossterm::style::Attribute::Reset)).unwrap();
+        use std::io::Write as _;
+        let mut ui = $crate::util::ui();
+        let _ = writeln!(ui, $($arg)*);
+        let _ = ui.flush();
     }};
 }
@@ -0,0 +1,115 @@
+mod common;
+
+use std::fs;
+
+use common::{run_gptxt_with_config, write_input};
+
+#[test]
+fn default_flag_satisfies_a_requirement() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a");
+
+    let output = run_gptxt_with_config(
+        dir.path(),
+        r#"default_flags = ["--json"]"#,
+        "result = {'k': data}",
+        &["--json-one-line", "--input", &input],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "{\"k\":\"a\"}\n");
+
+    let output = run_gptxt_with_config(
+        dir.path(),
+        r#"default_flags = ["--sample-lines", "2"]"#,
+        "result = data",
+        &["--seed", "7", "--input", &input],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn command_line_flag_overrides_a_conflicting_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a\nb\nc");
+
+    let output = run_gptxt_with_config(
+        dir.path(),
+        r#"default_flags = ["--show-lines", "3"]"#,
+        "result = data",
+        &["--tail-lines", "2", "--input", &input],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let out_dir = dir.path().join("out");
+    let output = run_gptxt_with_config(
+        dir.path(),
+        r#"default_flags = ["--json"]"#,
+        "result = {'a.txt': data}",
+        &["--split-output", out_dir.to_str().unwrap(), "--input", &input],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read_to_string(out_dir.join("a.txt")).unwrap(), "a\nb\nc");
+}
+
+#[test]
+fn conflicting_command_line_flags_still_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a");
+
+    let output = run_gptxt_with_config(dir.path(), "", "result = data", &["--show-lines", "1", "--tail-lines", "1", "--input", &input]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn split_output_rejects_non_string_contents_before_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a");
+    let out_dir = dir.path().join("out");
+
+    let output = run_gptxt_with_config(
+        dir.path(),
+        "",
+        "result = {'a.txt': data, 'b.txt': None}",
+        &["--split-output", out_dir.to_str().unwrap(), "--input", &input],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("NoneType for key \"b.txt\""));
+    assert!(!out_dir.join("a.txt").exists());
+}
+
+#[test]
+fn from_bundle_runs_with_the_bundled_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a b");
+    let bundle = dir.path().join("bundle.json");
+    let program = "out = who + ': ' + data\n";
+    let options = serde_json::json!({
+        "vars": {"who": "bundled"},
+        "result_var": "out",
+        "json": false,
+        "json_one_line": false,
+        "split_output": null,
+        "line_endings": "crlf",
+        "strict_result": true,
+        "result_hook": null,
+        "safe": false,
+        "allow_modules": [],
+        "timeout": null,
+    });
+    let contents = serde_json::json!({
+        "bundle_version": 2,
+        "task": "split words",
+        "model": "gpt-3.5-turbo",
+        "temperature": 0.0,
+        "max_tokens": 100,
+        "prompt": "",
+        "program": program,
+        "input": "unused",
+        "options": options,
+    });
+    fs::write(&bundle, contents.to_string()).unwrap();
+
+    let output = run_gptxt_with_config(dir.path(), "", "", &["--from-bundle", bundle.to_str().unwrap(), "--input", &input]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bundled: a b\r\n");
+}
@@ -0,0 +1,39 @@
+// Each test binary uses only some of these.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Runs gptxt on a program given on STDIN, with a config of its own so nothing calls the API or
+/// touches the user's files.
+pub fn run_gptxt(dir: &Path, program: &str, args: &[&str]) -> Output {
+    run_gptxt_with_config(dir, "", program, args)
+}
+
+/// Like `run_gptxt`, with `config` added to the config file after the key.
+pub fn run_gptxt_with_config(dir: &Path, config: &str, program: &str, args: &[&str]) -> Output {
+    let config_path = dir.join("config.toml");
+    fs::write(&config_path, format!("key = \"unused\"\n{}", config)).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gptxt"))
+        .arg("--program-stdin")
+        .arg("--config")
+        .arg(&config_path)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(program.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Writes `contents` to `name` in `dir` and returns the path, for `--input`.
+pub fn write_input(dir: &Path, name: &str, contents: &str) -> String {
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_owned()
+}
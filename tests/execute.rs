@@ -0,0 +1,142 @@
+use gptxt::generate::apply_output_wrappers;
+use gptxt::{execute_program, ExecOptions, ExecuteError, GenerateOptions};
+
+/// Runs `program` with the default options.
+async fn run(input: &str, program: &str) -> Result<String, ExecuteError> {
+    execute_program(input, program, &ExecOptions::default()).await
+}
+
+#[tokio::test]
+async fn returns_result() {
+    let result = run("a b c", "result = data.upper()").await;
+    assert_eq!(result.unwrap(), "A B C");
+}
+
+#[tokio::test]
+async fn missing_result_is_an_error() {
+    let result = run("a b c", "words = data.split()").await;
+    assert!(matches!(result, Err(ExecuteError::ResultNotFound(_))), "{:?}", result);
+}
+
+#[tokio::test]
+async fn printed_output_stands_in_for_missing_result() {
+    let result = run("a b c", "print(data.upper())").await;
+    assert_eq!(result.unwrap(), "A B C");
+}
+
+#[tokio::test]
+async fn exception_is_an_execution_error_with_traceback() {
+    let result = run("", "result = 1 / 0").await;
+    match result {
+        Err(ExecuteError::ExecutionError(traceback)) => assert!(traceback.contains("ZeroDivisionError"), "{}", traceback),
+        other => panic!("expected an execution error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn syntax_error_is_a_compile_error() {
+    let result = run("", "result = (").await;
+    assert!(matches!(result, Err(ExecuteError::CompileError(_))), "{:?}", result);
+}
+
+#[tokio::test]
+async fn non_string_results_are_converted() {
+    assert_eq!(run("", "result = 42").await.unwrap(), "42");
+    assert_eq!(run("", "result = ['a', 1]").await.unwrap(), "a\n1");
+    assert_eq!(run("", "result = {'a': 1}").await.unwrap(), r#"{"a": 1}"#);
+}
+
+#[tokio::test]
+async fn unconvertible_result_is_a_conversion_error() {
+    let result = run("", "result = {1, 2}").await;
+    match result {
+        Err(ExecuteError::ResultConversionError(type_name)) => assert_eq!(type_name, "set"),
+        other => panic!("expected a conversion error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn strict_rejects_none_and_printed_output() {
+    let options = ExecOptions { strict: true, ..ExecOptions::default() };
+    let result = execute_program("", "result = None", &options).await;
+    assert!(matches!(result, Err(ExecuteError::ResultNone(_))), "{:?}", result);
+    let result = execute_program("x", "print(data)", &options).await;
+    assert!(matches!(result, Err(ExecuteError::ResultNotFound(_))), "{:?}", result);
+}
+
+#[tokio::test]
+async fn strict_rejects_warnings() {
+    let options = ExecOptions { strict: true, ..ExecOptions::default() };
+    let program = "import warnings\nwarnings.warn('deprecated')\nresult = data";
+    let result = execute_program("x", program, &options).await;
+    assert!(matches!(&result, Err(ExecuteError::Warning(traceback)) if traceback.contains("deprecated")), "{:?}", result);
+    assert_eq!(run("x", program).await.unwrap(), "x");
+}
+
+#[tokio::test]
+async fn backslash_sequences_round_trip() {
+    let input = r"C:\new\report.txt matches \d+\n";
+    assert_eq!(run(input, "result = data").await.unwrap(), input);
+    assert_eq!(run(input, r"result = data + '\\r\\n' + '\r\n'").await.unwrap(), format!("{}\\r\\n\r\n", input));
+}
+
+#[tokio::test]
+async fn vars_are_set_next_to_data() {
+    let vars = [("delimiter".to_owned(), ";".to_owned()), ("filter".to_owned(), "a=b".to_owned())];
+    let options = ExecOptions { vars: &vars, ..ExecOptions::default() };
+    let program = "result = delimiter.join([data, filter])";
+    assert_eq!(execute_program("x", program, &options).await.unwrap(), "x;a=b");
+}
+
+#[tokio::test]
+async fn result_var_names_the_result() {
+    let options = ExecOptions { result_var: Some("output"), ..ExecOptions::default() };
+    let result = execute_program("x", "output = data * 2", &options).await;
+    assert_eq!(result.unwrap(), "xx");
+    let result = execute_program("x", "result = data", &options).await;
+    assert!(matches!(&result, Err(ExecuteError::ResultNotFound(name)) if name == "output"), "{:?}", result);
+}
+
+/// Generation options that only ask for an output format, for `apply_output_wrappers`.
+fn format_options(jsonify: bool, split_output: bool) -> GenerateOptions {
+    GenerateOptions {
+        task: String::new(),
+        temperature: 0.0,
+        max_tokens: 1,
+        system_message: None,
+        instructions: Vec::new(),
+        no_system_stdin_line: false,
+        show_lines: None,
+        squash_blank_lines: false,
+        show_head_tail: None,
+        tail_lines: None,
+        sample_lines: None,
+        seed: 0,
+        jsonify,
+        jsonify_one_line: false,
+        split_output,
+        result_var: "result".to_owned(),
+        vars: Vec::new(),
+        fixture: None,
+        trace: None,
+        quiet: false,
+    }
+}
+
+#[tokio::test]
+async fn wrappers_follow_a_program_without_a_trailing_newline() {
+    let program = apply_output_wrappers(&format_options(true, false), "result = {'a': data}");
+    assert_eq!(run("x", &program).await.unwrap(), r#"{"a": "x"}"#);
+    let program = apply_output_wrappers(&format_options(false, true), "result = {'a.txt': data}");
+    assert_eq!(run("x", &program).await.unwrap(), r#"{"a.txt": "x"}"#);
+}
+
+#[tokio::test]
+async fn wrappers_work_when_the_program_imports_or_rebinds_json() {
+    for options in [format_options(true, false), format_options(false, true)] {
+        let program = apply_output_wrappers(&options, "import json\nresult = json.loads(data)\n");
+        assert_eq!(run(r#"{"a.txt": "x"}"#, &program).await.unwrap(), r#"{"a.txt": "x"}"#);
+        let program = apply_output_wrappers(&options, "json = None\nresult = {'a.txt': data}");
+        assert_eq!(run("x", &program).await.unwrap(), r#"{"a.txt": "x"}"#);
+    }
+}
@@ -0,0 +1,45 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use gptxt::export::standalone_python;
+use gptxt::util::LineEnding;
+use gptxt::ExecOptions;
+
+/// Runs an exported script with python3 on `input`, returning its stdout, or `None` without python3.
+fn run_script(script: &str, input: &str) -> Option<String> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("script.py");
+    std::fs::write(&path, script).unwrap();
+    let mut child = Command::new("python3")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    Some(String::from_utf8(output.stdout).unwrap())
+}
+
+#[test]
+fn program_follows_the_harness_as_written() {
+    let program = "words = data.split()\nresult = ' '.join(reversed(words))";
+    let script = standalone_python("reverse", program, &ExecOptions::default(), LineEnding::Keep, None);
+    assert!(script.contains(&format!("{}\n__gptxt_finish(globals())\n", program)), "{}", script);
+}
+
+#[test]
+fn script_converts_results_and_applies_the_hook() {
+    let options = ExecOptions { result_hook: Some("result + [len(data)]"), ..Default::default() };
+    let script = standalone_python("count", "result = data.split()", &options, LineEnding::Keep, None);
+    let Some(output) = run_script(&script, "a b") else { return };
+    assert_eq!(output, "a\nb\n3\n");
+}
+
+#[test]
+fn script_falls_back_to_printed_output() {
+    let script = standalone_python("print", "print(data.upper())", &ExecOptions::default(), LineEnding::Keep, None);
+    let Some(output) = run_script(&script, "abc") else { return };
+    assert_eq!(output, "ABC\n");
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+
+use common::{run_gptxt, write_input};
+
+fn make_fifo(dir: &Path, name: &str) -> String {
+    let path = dir.join(name);
+    mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+#[test]
+fn output_to_fifo() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a b c");
+    let fifo = make_fifo(dir.path(), "out");
+
+    let reader = {
+        let fifo = fifo.clone();
+        thread::spawn(move || fs::read_to_string(fifo).unwrap())
+    };
+    let output = run_gptxt(dir.path(), "result = data.upper()", &["--input", &input, "--output", &fifo]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(reader.join().unwrap(), "A B C\n");
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn input_from_fifo() {
+    let dir = tempfile::tempdir().unwrap();
+    let fifo = make_fifo(dir.path(), "in");
+
+    let writer = {
+        let fifo = fifo.clone();
+        thread::spawn(move || fs::write(fifo, "a b c").unwrap())
+    };
+    let output = run_gptxt(dir.path(), "result = data.upper()", &["--input", &fifo]);
+    writer.join().unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "A B C\n");
+}
+
+#[test]
+fn output_to_dev_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a b c");
+
+    let output = run_gptxt(dir.path(), "result = data.upper()", &["--input", &input, "--output", "/dev/stdout"]);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "A B C\n");
+}
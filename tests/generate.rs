@@ -0,0 +1,11 @@
+use std::time::Duration;
+
+use gptxt::generate::retry_delay;
+
+#[test]
+fn retry_delay_doubles_up_to_the_cap() {
+    assert_eq!(retry_delay(0), Duration::from_secs(1));
+    assert_eq!(retry_delay(1), Duration::from_secs(1));
+    assert_eq!(retry_delay(3), Duration::from_secs(4));
+    assert_eq!(retry_delay(20), Duration::from_secs(30));
+}
@@ -0,0 +1,30 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use gptxt::input::read_file;
+
+#[test]
+fn gzipped_files_are_decompressed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input");
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(b"a\nb\n").unwrap();
+    fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+    // Recognized by its magic bytes, without `gzip` or a .gz name.
+    assert_eq!(read_file(&path, None, false, false).unwrap(), "a\nb\n");
+}
+
+#[test]
+fn oversized_input_is_an_error_unless_truncated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input.txt");
+    fs::write(&path, "héllo").unwrap();
+
+    let err = read_file(&path, Some(2), false, false).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    // Cut before the two-byte 'é' rather than through it.
+    assert_eq!(read_file(&path, Some(2), true, false).unwrap(), "h");
+}
@@ -0,0 +1,75 @@
+//! Runs that go through a child process. The child is the gptxt binary itself, so these run it
+//! rather than calling `isolate` from the test harness.
+
+mod common;
+
+use common::{run_gptxt, write_input};
+
+#[test]
+fn timeout_stops_a_program_that_runs_too_long() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a");
+
+    let output = run_gptxt(dir.path(), "while True:\n    pass", &["--timeout", "1", "--input", &input]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("did not finish within 1s"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn timeout_keeps_the_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a");
+
+    let program = "import os\nresult = 'set' if os.environ.get('PATH') else 'unset'";
+    let output = run_gptxt(dir.path(), program, &["--timeout", "30", "--input", &input]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "set\n");
+}
+
+#[test]
+fn safe_blocks_imports_outside_the_allowlist() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a");
+
+    let output = run_gptxt(dir.path(), "import subprocess\nresult = data", &["--safe", "--input", &input]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("import of 'subprocess' is blocked"));
+}
+
+#[test]
+fn allow_module_extends_the_allowlist() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a");
+    let program = "import base64\nresult = base64.b64encode(data.encode()).decode()";
+
+    let output = run_gptxt(dir.path(), program, &["--safe", "--input", &input]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("import of 'base64' is blocked"));
+
+    let output = run_gptxt(dir.path(), program, &["--safe", "--allow-module", "base64", "--input", &input]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "YQ==\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn safe_denies_opening_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = write_input(dir.path(), "input.txt", "a");
+    let secret = write_input(dir.path(), "secret.txt", "secret");
+    let program = format!("result = open({:?}).read()", secret);
+
+    let output = run_gptxt(dir.path(), &program, &["--input", &input]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "secret\n");
+
+    let output = run_gptxt(dir.path(), &program, &["--safe", "--input", &input]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("PermissionError"));
+    assert!(output.stdout.is_empty());
+
+    // Getting around the import check still leaves the files out of reach.
+    let program = format!("g = {{'__name__': 'x'}}\nexec('import os', g)\nresult = str(g['os'].listdir({:?}))", dir.path());
+    let output = run_gptxt(dir.path(), &program, &["--safe", "--input", &input]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("PermissionError"));
+}
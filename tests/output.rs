@@ -0,0 +1,34 @@
+use std::fs;
+
+use gptxt::output::{validate_json_schema, write_split_output};
+use gptxt::util::LineEnding;
+use gptxt::ExecuteError;
+use jsonschema::JSONSchema;
+
+#[test]
+fn split_output_writes_each_file() {
+    let dir = tempfile::tempdir().unwrap();
+    write_split_output(dir.path(), r#"{"a.txt": "a\n", "sub/b.txt": "b"}"#, LineEnding::Crlf).unwrap();
+    assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "a\r\n");
+    assert_eq!(fs::read_to_string(dir.path().join("sub/b.txt")).unwrap(), "b");
+}
+
+#[test]
+fn split_output_writes_nothing_if_any_file_is_invalid() {
+    let dir = tempfile::tempdir().unwrap();
+    let result = write_split_output(dir.path(), r#"{"a.txt": "a", "../b.txt": "b"}"#, LineEnding::Keep);
+    assert!(matches!(result, Err(ExecuteError::SplitOutputError(_))), "{:?}", result);
+    assert!(!dir.path().join("a.txt").exists());
+}
+
+#[test]
+fn schema_errors_point_at_the_value() {
+    let schema = JSONSchema::compile(&serde_json::json!({
+        "type": "object",
+        "properties": {"n": {"type": "integer"}},
+    }))
+    .unwrap();
+    assert!(validate_json_schema(&schema, r#"{"n": 1}"#).is_ok());
+    let result = validate_json_schema(&schema, r#"{"n": "one"}"#);
+    assert!(matches!(&result, Err(ExecuteError::SchemaError(e)) if e.contains("/n")), "{:?}", result);
+}
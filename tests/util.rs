@@ -0,0 +1,49 @@
+use gptxt::util::{normalize_line_endings, LineEnding};
+
+#[test]
+fn line_endings_parse() {
+    assert_eq!("keep".parse::<LineEnding>(), Ok(LineEnding::Keep));
+    assert_eq!("lf".parse::<LineEnding>(), Ok(LineEnding::Lf));
+    assert_eq!("crlf".parse::<LineEnding>(), Ok(LineEnding::Crlf));
+    assert!("CRLF".parse::<LineEnding>().is_err());
+    assert!("".parse::<LineEnding>().is_err());
+}
+
+#[test]
+fn keep_leaves_line_endings_alone() {
+    let mixed = "a\r\nb\nc\rd\r\n";
+    assert_eq!(normalize_line_endings(mixed, LineEnding::Keep), mixed);
+}
+
+#[test]
+fn lf_converts_crlf_only() {
+    assert_eq!(normalize_line_endings("a\r\nb\nc", LineEnding::Lf), "a\nb\nc");
+    assert_eq!(normalize_line_endings("a\r\n\r\n", LineEnding::Lf), "a\n\n");
+    // A lone CR isn't a line ending on its own.
+    assert_eq!(normalize_line_endings("a\rb\r\n", LineEnding::Lf), "a\rb\n");
+}
+
+#[test]
+fn crlf_converts_every_line_ending() {
+    assert_eq!(normalize_line_endings("a\r\nb\nc", LineEnding::Crlf), "a\r\nb\r\nc");
+    assert_eq!(normalize_line_endings("a\n\n", LineEnding::Crlf), "a\r\n\r\n");
+    assert_eq!(normalize_line_endings("a\rb\n", LineEnding::Crlf), "a\rb\r\n");
+}
+
+#[test]
+fn modes_round_trip() {
+    let mixed = "one\r\ntwo\nthree\r\n\nfour";
+    let lf = normalize_line_endings(mixed, LineEnding::Lf);
+    let crlf = normalize_line_endings(mixed, LineEnding::Crlf);
+    assert_eq!(normalize_line_endings(&crlf, LineEnding::Lf), lf);
+    assert_eq!(normalize_line_endings(&lf, LineEnding::Crlf), crlf);
+    assert_eq!(normalize_line_endings(&lf, LineEnding::Lf), lf);
+    assert_eq!(normalize_line_endings(&crlf, LineEnding::Crlf), crlf);
+}
+
+#[test]
+fn terminator_follows_the_mode() {
+    assert_eq!(LineEnding::Keep.as_str(), "\n");
+    assert_eq!(LineEnding::Lf.as_str(), "\n");
+    assert_eq!(LineEnding::Crlf.as_str(), "\r\n");
+}